@@ -10,12 +10,15 @@ import numpy as np
 class ImgColor(Enum):
     GRAY = 0,
     RGB = 1,
-    DYNAMIC = 2
+    DYNAMIC = 2,  # native channel count of the file
+    RGBA = 3,
+    GRAYA = 4
 
 
 class ImgFormat(Enum):
     U8 = 0,
-    F32 = 1
+    F32 = 1,  # decoded from the source bit depth
+    U16 = 2
 
 
 class TypeNoise(Enum):
@@ -56,12 +59,242 @@ class TypeDot(Enum):
 def read(
         path: str,
         mode: Optional[ImgColor] = ImgColor.DYNAMIC,
-        format: Optional[ImgFormat] = ImgFormat.U8
+        format: Optional[ImgFormat] = ImgFormat.U8,
+        apply_orientation: Optional[bool] = False,
+        to_srgb: Optional[bool] = False,
+        scale: Optional[int] = 1
 ) -> np.ndarray:
     """ The function to read the image. input parameters:
     \n path -> str file path 
-    \n mode -> psd dynamic format, and in other cases rgb, None = ImgColor.DYNAMIC
-    \n format -> f32 0-1 img, u8 0-255, None = ImgFormat.U8"""
+    \n mode -> gray, rgb, rgba, gray+alpha or dynamic (native channel count of the file), None = ImgColor.DYNAMIC
+    \n format -> f32 0-1 img, u8 0-255, u16 0-65535, None = ImgFormat.U8
+    \n f32 and u16 keep the precision of 16 bit and 32 bit float sources (png, tiff, psd)
+    \n cmyk, lab, multichannel, indexed and bitmap psd and cmyk jpeg are converted to rgb/gray (cmyk through the
+    embedded icc profile), dynamic returns the native channels of cmyk (ink 0-1, 0 = no ink, + alpha), lab
+    (L / 100, (a + 128) / 255, (b + 128) / 255) and multichannel instead
+    \n apply_orientation -> rotate/flip the image upright by its exif orientation (see read_info), None = False
+    \n to_srgb -> convert rgb/gray pixels from the embedded icc profile (e.g. Adobe RGB, Dot Gain 20%) to sRGB, None = False
+    \n scale -> 2, 4 or 8 reads at 1/scale of the size (rounded up) for previews, baseline jpeg is decoded at that size,
+    other formats are box resized after decoding, None = 1"""
+
+
+def read_region(
+        path: str,
+        x: int,
+        y: int,
+        w: int,
+        h: int,
+        mode: Optional[ImgColor] = ImgColor.DYNAMIC,
+        format: Optional[ImgFormat] = ImgFormat.U8
+) -> np.ndarray:
+    """ The function to read a rectangle of an image, e.g. a strip of a very tall webtoon page. input parameters:
+    \n path -> str file path
+    \n x, y -> top left corner in stored pixels, exif orientation is not applied
+    \n w, h -> size of the rectangle, it has to lie inside the image
+    \n mode, format -> same as read
+    \n psd and png only decode the rows down to the rectangle, tiff only the tiles/strips it covers,
+    other formats are decoded in full and cropped"""
+
+
+class StripReader:
+    width: int
+    height: int
+
+    def __iter__(self) -> "StripReader": ...
+
+    def __next__(self) -> tuple[int, np.ndarray]: ...
+
+
+def read_strips(
+        path: str,
+        height: int,
+        overlap: Optional[int] = 0,
+        mode: Optional[ImgColor] = ImgColor.DYNAMIC,
+        format: Optional[ImgFormat] = ImgFormat.U8
+) -> StripReader:
+    """ The function to read an image in consecutive horizontal strips with bounded memory, e.g. to run
+    fast_color_level, screentone or resize_img on very tall webtoon pages. input parameters:
+    \n path -> str file path
+    \n height -> rows per strip, the last strip can be shorter
+    \n overlap -> extra rows of the neighbouring strips above and below every strip, None = 0
+    \n mode, format -> same as read
+    \n yields (y, strip) with y the first image row of the strip (overlap included), png rows and baseline
+    jpeg mcu rows are streamed and psd rows are read from the file per strip, other formats (interlaced png,
+    progressive jpeg...) raise an error"""
+
+
+def read_bytes(
+        data: bytes,
+        mode: Optional[ImgColor] = ImgColor.DYNAMIC,
+        format: Optional[ImgFormat] = ImgFormat.U8,
+        apply_orientation: Optional[bool] = False,
+        to_srgb: Optional[bool] = False,
+        scale: Optional[int] = 1
+) -> np.ndarray:
+    """ Decode an image from memory (archive entries, HTTP responses) instead of a file path.
+    \n data -> bytes of an encoded image, psd or any format supported by read
+    \n mode -> same as read, None = ImgColor.DYNAMIC
+    \n format -> same as read, None = ImgFormat.U8
+    \n apply_orientation -> same as read, None = False
+    \n to_srgb -> same as read, None = False
+    \n scale -> same as read, None = 1"""
+
+
+def read_batch(
+        paths: list[str],
+        mode: Optional[ImgColor] = ImgColor.DYNAMIC,
+        format: Optional[ImgFormat] = ImgFormat.U8,
+        threads: Optional[int] = None,
+        apply_orientation: Optional[bool] = False,
+        to_srgb: Optional[bool] = False,
+        scale: Optional[int] = 1
+) -> tuple[list[Optional[np.ndarray]], list[Optional[str]]]:
+    """ Read many images in parallel on a rust thread pool with the GIL released.
+    \n paths -> list of file paths
+    \n mode -> same as read, None = ImgColor.DYNAMIC
+    \n format -> same as read, None = ImgFormat.U8
+    \n threads -> number of worker threads, None = one per core
+    \n apply_orientation -> same as read, None = False
+    \n to_srgb -> same as read, None = False
+    \n scale -> same as read, None = 1
+    \n returns (arrays, errors) in the order of paths, a failed file has None in arrays and its message in errors"""
+
+
+
+def read_icc(path: str) -> Optional[bytes]:
+    """ Read the embedded icc profile (png, jpeg, tiff, webp, psd), None if the image has none.
+    \n path -> image file path"""
+
+def read_frame_count(path: str) -> int:
+    """ Read the number of frames without decoding: pages of a multi-page tiff, frames of an animated gif/webp/png, 1 for other images.
+    \n path -> image file path"""
+
+
+def read_frame(
+        path: str,
+        index: int,
+        mode: Optional[ImgColor] = ImgColor.DYNAMIC,
+        format: Optional[ImgFormat] = ImgFormat.U8
+) -> np.ndarray:
+    """ Decode one frame or page, animation frames are composited to the full canvas.
+    \n path -> image file path
+    \n index -> frame index, frame 0 of a still image is the image
+    \n mode -> same as read, None = ImgColor.DYNAMIC
+    \n format -> same as read, None = ImgFormat.U8"""
+
+
+def read_frames(
+        path: str,
+        mode: Optional[ImgColor] = ImgColor.DYNAMIC,
+        format: Optional[ImgFormat] = ImgFormat.U8
+) -> tuple[list[np.ndarray], list[int]]:
+    """ Decode all frames or pages.
+    \n path -> image file path
+    \n mode -> same as read, None = ImgColor.DYNAMIC
+    \n format -> same as read, None = ImgFormat.U8
+    \n returns (arrays, delays), delays are the display time of every frame in milliseconds, 0 for tiff pages and still images"""
+
+def read_cbz_pages(path: str) -> list[str]:
+    """ List the pages of a comic archive (cbz/zip): its image entries in natural sort order (page2 before page10).
+    \n path -> archive file path"""
+
+
+def read_cbz(
+        path: str,
+        page: int,
+        mode: Optional[ImgColor] = ImgColor.DYNAMIC,
+        format: Optional[ImgFormat] = ImgFormat.U8,
+        apply_orientation: Optional[bool] = False,
+        to_srgb: Optional[bool] = False,
+        scale: Optional[int] = 1
+) -> np.ndarray:
+    """ Decode one page of a comic archive without extracting it to disk.
+    \n path -> archive file path
+    \n page -> index into read_cbz_pages
+    \n mode -> same as read, None = ImgColor.DYNAMIC
+    \n format -> same as read, None = ImgFormat.U8
+    \n apply_orientation -> same as read, None = False
+    \n to_srgb -> same as read, None = False
+    \n scale -> same as read, None = 1"""
+
+
+def read_cbz_batch(
+        path: str,
+        mode: Optional[ImgColor] = ImgColor.DYNAMIC,
+        format: Optional[ImgFormat] = ImgFormat.U8,
+        threads: Optional[int] = None,
+        apply_orientation: Optional[bool] = False,
+        to_srgb: Optional[bool] = False,
+        scale: Optional[int] = 1
+) -> tuple[list[Optional[np.ndarray]], list[Optional[str]]]:
+    """ Decode all pages of a comic archive in parallel on a rust thread pool with the GIL released.
+    \n path -> archive file path
+    \n mode -> same as read, None = ImgColor.DYNAMIC
+    \n format -> same as read, None = ImgFormat.U8
+    \n threads -> number of worker threads, None = one per core
+    \n apply_orientation -> same as read, None = False
+    \n to_srgb -> same as read, None = False
+    \n scale -> same as read, None = 1
+    \n returns (arrays, errors) in page order like read_batch"""
+
+class PsdLayer:
+    name: str
+    kind: str  # "pixel", "text", "group" or "group_end"
+    visible: bool
+    opacity: int  # 0-255
+    blend_mode: str  # photoshop blend mode key: "norm", "mul", "scrn", "pass"...
+    offset: tuple[int, int]  # (x, y) of the layer on the canvas
+    data: np.ndarray
+
+    def __init__(
+            self,
+            data: Optional[np.ndarray] = None,
+            name: Optional[str] = "Layer",
+            opacity: Optional[int] = 255,
+            visible: Optional[bool] = True,
+            blend_mode: Optional[str] = "norm",
+            offset: Optional[tuple[int, int]] = (0, 0),
+            kind: Optional[str] = "pixel"
+    ) -> None: ...
+
+
+def read_psd_layers(
+        path: str,
+        mode: Optional[ImgColor] = ImgColor.DYNAMIC,
+        format: Optional[ImgFormat] = ImgFormat.U8
+) -> list[PsdLayer]:
+    """
+    Reads the layers of a psd file, bottom to top as they are stored in the file.
+
+    Arguments:
+    path -- path to the psd file.
+    mode -- color mode of the layer pixels, ImgColor.DYNAMIC keeps the document channels plus the layer transparency (cmyk and lab layers are converted to rgba).
+    format -- sample format of the layer pixels.
+
+    Examples:
+    layers = read_psd_layers("page.psd")
+    art = [layer for layer in layers if layer.kind != "text"]
+    """
+
+
+def save_psd(
+        layers: list[PsdLayer],
+        path: str,
+        bit_depth: Optional[int] = None,
+        size: Optional[tuple[int, int]] = None
+) -> None:
+    """
+    Writes a layered psd file with a flattened composite.
+
+    Arguments:
+    layers -- layers bottom to top, "group" and "group_end" layers open and close a layer group (the group comes after its content).
+    path -- path of the psd file.
+    bit_depth -- 8 or 16, by default 16 when a layer is an uint16 array, else 8.
+    size -- (width, height) of the canvas, by default the extent of the layers.
+
+    Examples:
+    save_psd([PsdLayer(background, name="bg"), PsdLayer(text, name="text", blend_mode="mul")], "page.psd")
+    """
 
 
 def read_size(path: str) -> tuple[int, int]:
@@ -84,6 +317,40 @@ def read_size(path: str) -> tuple[int, int]:
     """
 
 
+class ImgInfo:
+    width: int
+    height: int
+    channels: int  # channels stored in the file, including alpha and extra psd channels
+    bit_depth: int  # bits per channel
+    color_mode: str  # "gray", "gray_alpha", "rgb", "rgba", "cmyk", "lab", "indexed", "bitmap"...
+    format: str  # "png", "jpeg", "psd", "psb"...
+    dpi: Optional[tuple[float, float]]
+    has_icc_profile: bool
+    orientation: Optional[int]  # exif orientation 1-8
+
+
+def read_info(path: str) -> ImgInfo:
+    """
+    Reads the image properties from the file headers without decoding the pixels.
+
+    Arguments:
+    path -- A string that holds the path to the image file.
+
+    Returns:
+    An ImgInfo with the width, height, channel count, bit depth, color mode
+    (including psd cmyk, lab and indexed modes), format name, dpi, whether an icc
+    profile is embedded and the exif orientation.
+
+    Examples:
+    info = read_info("path/to/image.psd")
+    if info.color_mode == "cmyk":
+        print(f"{info.width}x{info.height} cmyk page")
+
+    Errors:
+    This function will raise an error if the file does not exist or the headers can not be parsed.
+    """
+
+
 def screentone(
         array: np.ndarray,
         dot_size: int,
@@ -160,6 +427,47 @@ def crop_cord(array: np.ndarray) -> (
     """returns image coordinates not equal to 0, made for cropping using the Laplace operator"""
 
 
+def split_webtoon(
+        array: np.ndarray,
+        min_height: int,
+        max_height: int,
+        tolerance: Optional[float] = 0.0
+) -> list[int]:
+    """ The function to find where to cut a long webtoon strip (f32, 2D or 3D) into pages of min_height to
+    max_height rows: cuts go to the middle of the longest run of uniform rows (every pixel within tolerance of
+    the first pixel of the row), at max_height when there is none. returns the rows where the pages after the
+    first start, the last page can be shorter than min_height"""
+
+
+def stitch(arrays: list[np.ndarray]) -> np.ndarray:
+    """stacks f32 strips with the same width and channels top to bottom, e.g. pages cut by split_webtoon"""
+
+
+def spread_cord(
+        array: np.ndarray,
+        rtl: Optional[bool] = False,
+        min_ratio: Optional[float] = 1.0,
+        tolerance: Optional[float] = 0.0,
+        require_gutter: Optional[bool] = True
+) -> list[tuple[int, int, int, int]]:
+    """ The function to detect a double-page spread (f32, 2D or 3D): a page at least min_ratio times wider than
+    tall with a run of uniform columns (every pixel within tolerance of the first pixel of the column) in the
+    central tenth is split in the middle of that run, without require_gutter pages with no such run are split in
+    the center. returns (x_min, x_max, y_min, y_max) like crop_cord for both pages in reading order, the right
+    page first with rtl (manga), or for the whole page when it is not a spread"""
+
+
+def split_spread(
+        array: np.ndarray,
+        rtl: Optional[bool] = False,
+        min_ratio: Optional[float] = 1.0,
+        tolerance: Optional[float] = 0.0,
+        require_gutter: Optional[bool] = True
+) -> list[np.ndarray]:
+    """splits a double-page spread into its pages in reading order, see spread_cord.
+    returns the whole page alone when it is not a spread"""
+
+
 def fast_color_level(
         array: np.ndarray,
         in_low: Optional[int] = 0,
@@ -198,13 +506,105 @@ class ResizeFilters(enum):
     Gaussian = 6,
     Lanczos3 = 7
 
+class SaveFormat(Enum):
+    PNG = 0,
+    JPEG = 1,
+    WEBP = 2,
+    TIFF = 3,
+    BMP = 4
+
+
+class ChromaSubsampling(Enum):
+    YUV444 = 0,
+    YUV422 = 1,
+    YUV420 = 2
+
+
+class PngFilter(Enum):
+    NoFilter = 0,
+    Sub = 1,
+    Up = 2,
+    Avg = 3,
+    Paeth = 4,
+    Adaptive = 5
+
+
+class TypeDither(Enum):
+    NONE = 0,  # plain rounding
+    ORDERED = 1,  # 8x8 bayer matrix
+    BLUENOISE = 2  # 64x64 blue noise texture
+
+
+class SaveOptions:
+    """Encoder settings, options that do not apply to the output format are ignored."""
+    quality: Optional[int]  # jpeg and lossy webp quality 1-100, None = 75
+    subsampling: Optional[ChromaSubsampling]  # jpeg chroma subsampling, None = 4:2:0
+    compression: Optional[int]  # png compression level 0 (none) - 9 (best), None = fast
+    png_filter: Optional[PngFilter]  # png row filter, None = Adaptive
+    lossless: Optional[bool]  # webp lossless encoding, None = True
+    bit_depth: Optional[int]  # 8 or 16 bits per channel (png, tiff), None = 16 for u16 arrays, 8 otherwise
+    dither: Optional[TypeDither]  # dithering when float arrays are quantized, None = TypeDither.NONE
+    bilevel: Optional[bool]  # binary gray images as 1 bit png or g4 tiff, None = False
+    indexed: Optional[bool]  # indexed png with an automatic (median cut) palette, alpha kept in a trns chunk, None = False
+    palette: Optional[list[tuple[int, int, int]]]  # rgb palette for an indexed png, nearest color mapping, not for images with alpha
+    colors: Optional[int]  # size 2-256 of the automatic palette, None = 256
+
+    def __init__(
+            self,
+            quality: Optional[int] = None,
+            subsampling: Optional[ChromaSubsampling] = None,
+            compression: Optional[int] = None,
+            png_filter: Optional[PngFilter] = None,
+            lossless: Optional[bool] = None,
+            bit_depth: Optional[int] = None,
+            dither: Optional[TypeDither] = None,
+            bilevel: Optional[bool] = None,
+            indexed: Optional[bool] = None,
+            palette: Optional[list[tuple[int, int, int]]] = None,
+            colors: Optional[int] = None
+    ) -> None: ...
+
+
 def save(
         array: np.ndarray,
-        path: str
-) -> np.ndarray:
+        path: str,
+        format: Optional[SaveFormat] = None,
+        options: Optional[SaveOptions] = None
+) -> None:
     """function to save an image, currently supports:
                   \n     f32 0-1 array
-                  \n     u8 0-255 array"""
+                  \n     u8 0-255 array
+                  \n     u16 0-65535 array
+                  \n     shapes (h,w), (h,w,1) gray, (h,w,2) gray+alpha, (h,w,3) rgb, (h,w,4) rgba
+                  \n floats are rounded and clamped to the output range, see SaveOptions.dither
+                  \n format -> output format, None = inferred from the path extension
+                  \n options -> SaveOptions encoder settings, None = encoder defaults"""
+
+
+def save_cbz(
+        pages: list[np.ndarray],
+        path: str,
+        format: Optional[SaveFormat] = SaveFormat.PNG,
+        options: Optional[SaveOptions] = None,
+        comic_info: Optional[dict[str, object]] = None
+) -> None:
+    """ Save pages to a comic archive (cbz), encoded in parallel with the save encoders and named 001, 002... in page order.
+    \n pages -> arrays like save takes
+    \n path -> cbz file path
+    \n format -> image format of the pages, None = SaveFormat.PNG
+    \n options -> SaveOptions of the encoder, None = defaults
+    \n comic_info -> ComicInfo.xml fields, e.g. {"Series": "Name", "Number": 1, "Manga": "YesAndRightToLeft"}, PageCount is filled in, None = no ComicInfo.xml"""
+
+def encode(
+        array: np.ndarray,
+        format: SaveFormat,
+        options: Optional[SaveOptions] = None
+) -> bytes:
+    """encode an image to bytes in memory instead of a file, same arrays and options as save
+                  \n format -> output format
+                  \n options -> SaveOptions encoder settings, None = encoder defaults"""
+
+
 def resize_img(
     input: np.ndarray,
     size: tuple[int,int],
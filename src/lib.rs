@@ -2,7 +2,10 @@ use pyo3::prelude::*;
 
 use utils::functions::{color_function, core_funcion, halftone_function, img_function, img_resize};
 
-use crate::utils::core::enums::{CvtType, ImgColor, ImgFormat, ResizeFilters, TypeDot, TypeNoise};
+use crate::utils::core::enums::{
+    ChromaSubsampling, CvtType, ImgColor, ImgFormat, PngFilter, ResizeFilters, SaveFormat, TypeDither,
+    TypeDot, TypeNoise,
+};
 
 mod utils;
 
@@ -10,11 +13,31 @@ mod utils;
 #[pymodule]
 fn pepeline(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(img_function::read, m)?)?;
+    m.add_function(wrap_pyfunction!(img_function::read_region, m)?)?;
+    m.add_function(wrap_pyfunction!(img_function::read_strips, m)?)?;
+    m.add_function(wrap_pyfunction!(img_function::read_bytes, m)?)?;
     m.add_function(wrap_pyfunction!(img_function::read_size, m)?)?;
+    m.add_function(wrap_pyfunction!(img_function::read_info, m)?)?;
+    m.add_function(wrap_pyfunction!(img_function::read_batch, m)?)?;
+    m.add_function(wrap_pyfunction!(img_function::read_icc, m)?)?;
+    m.add_function(wrap_pyfunction!(img_function::read_frame_count, m)?)?;
+    m.add_function(wrap_pyfunction!(img_function::read_frame, m)?)?;
+    m.add_function(wrap_pyfunction!(img_function::read_frames, m)?)?;
+    m.add_function(wrap_pyfunction!(img_function::read_cbz_pages, m)?)?;
+    m.add_function(wrap_pyfunction!(img_function::read_cbz, m)?)?;
+    m.add_function(wrap_pyfunction!(img_function::read_cbz_batch, m)?)?;
+    m.add_function(wrap_pyfunction!(img_function::read_psd_layers, m)?)?;
+    m.add_function(wrap_pyfunction!(img_function::save_psd, m)?)?;
+    m.add_function(wrap_pyfunction!(img_function::save_cbz, m)?)?;
     m.add_function(wrap_pyfunction!(halftone_function::screentone, m)?)?;
     m.add_function(wrap_pyfunction!(core_funcion::noise_generate, m)?)?;
     m.add_function(wrap_pyfunction!(img_function::save, m)?)?;
+    m.add_function(wrap_pyfunction!(img_function::encode, m)?)?;
     m.add_function(wrap_pyfunction!(core_funcion::crop_cord, m)?)?;
+    m.add_function(wrap_pyfunction!(core_funcion::split_webtoon, m)?)?;
+    m.add_function(wrap_pyfunction!(core_funcion::stitch, m)?)?;
+    m.add_function(wrap_pyfunction!(core_funcion::spread_cord, m)?)?;
+    m.add_function(wrap_pyfunction!(core_funcion::split_spread, m)?)?;
     m.add_function(wrap_pyfunction!(core_funcion::best_tile, m)?)?;
     m.add_function(wrap_pyfunction!(img_resize::resize_img, m)?)?;
     m.add_function(wrap_pyfunction!(color_function::fast_color_level, m)?)?;
@@ -25,5 +48,13 @@ fn pepeline(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<ImgColor>()?;
     m.add_class::<ImgFormat>()?;
     m.add_class::<ResizeFilters>()?;
+    m.add_class::<SaveFormat>()?;
+    m.add_class::<ChromaSubsampling>()?;
+    m.add_class::<PngFilter>()?;
+    m.add_class::<TypeDither>()?;
+    m.add_class::<utils::image::save::SaveOptions>()?;
+    m.add_class::<img_function::PyPsdLayer>()?;
+    m.add_class::<img_function::PyStripReader>()?;
+    m.add_class::<utils::image::size_decode::ImgInfo>()?;
     Ok(())
 }
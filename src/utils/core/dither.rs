@@ -0,0 +1,141 @@
+use std::sync::OnceLock;
+
+use ndarray::ArrayViewD;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::utils::core::enums::TypeDither;
+
+const BAYER_SIZE: usize = 8;
+const BLUE_NOISE_SIZE: usize = 64;
+
+/// 8x8 bayer matrix, ranks 0-63.
+fn bayer(x: usize, y: usize) -> u32 {
+    // the lowest coordinate bits select the most significant rank bits
+    let mut rank = 0;
+    for bit in 0..3 {
+        let (xb, yb) = ((x >> bit) & 1, (y >> bit) & 1);
+        rank = (rank << 2) | ((xb ^ yb) << 1 | yb) as u32;
+    }
+    rank
+}
+
+/// 64x64 blue noise ranks built once with the void-and-cluster method.
+fn blue_noise() -> &'static [u32] {
+    static TEXTURE: OnceLock<Vec<u32>> = OnceLock::new();
+    TEXTURE.get_or_init(|| void_and_cluster(BLUE_NOISE_SIZE, 1.5))
+}
+
+fn void_and_cluster(size: usize, sigma: f64) -> Vec<u32> {
+    let n = size * size;
+    // toroidal gaussian energy of a single point by (dx, dy)
+    let kernel: Vec<f64> = (0..n)
+        .map(|i| {
+            let (dx, dy) = (i % size, i / size);
+            let dx = dx.min(size - dx) as f64;
+            let dy = dy.min(size - dy) as f64;
+            (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp()
+        })
+        .collect();
+    let mut energy = vec![0.0; n];
+    let mut points = vec![false; n];
+    let toggle = |energy: &mut [f64], points: &mut [bool], i: usize, on: bool| {
+        points[i] = on;
+        let sign = if on { 1.0 } else { -1.0 };
+        let (px, py) = (i % size, i / size);
+        for (j, e) in energy.iter_mut().enumerate() {
+            let dx = (j % size + size - px) % size;
+            let dy = (j / size + size - py) % size;
+            *e += sign * kernel[dy * size + dx];
+        }
+    };
+    let tightest = |energy: &[f64], points: &[bool]| {
+        (0..n)
+            .filter(|&i| points[i])
+            .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+    };
+    let largest_void = |energy: &[f64], points: &[bool]| {
+        (0..n)
+            .filter(|&i| !points[i])
+            .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+    };
+
+    // initial pattern: a tenth of the pixels at random, relaxed until the tightest
+    // cluster is also the largest void
+    let mut rng = StdRng::seed_from_u64(0x5eed);
+    let mut ones = 0;
+    while ones < n / 10 {
+        let i = rng.gen_range(0..n);
+        if !points[i] {
+            toggle(&mut energy, &mut points, i, true);
+            ones += 1;
+        }
+    }
+    while let Some(cluster) = tightest(&energy, &points) {
+        toggle(&mut energy, &mut points, cluster, false);
+        let void = largest_void(&energy, &points).unwrap_or(cluster);
+        toggle(&mut energy, &mut points, void, true);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = vec![0; n];
+    let (prototype, prototype_energy) = (points.clone(), energy.clone());
+    // ranks below the prototype: remove the tightest clusters
+    for r in (0..ones).rev() {
+        if let Some(cluster) = tightest(&energy, &points) {
+            toggle(&mut energy, &mut points, cluster, false);
+            rank[cluster] = r as u32;
+        }
+    }
+    // ranks above the prototype: fill the largest voids
+    let (mut points, mut energy) = (prototype, prototype_energy);
+    for r in ones..n {
+        if let Some(void) = largest_void(&energy, &points) {
+            toggle(&mut energy, &mut points, void, true);
+            rank[void] = r as u32;
+        }
+    }
+    rank
+}
+
+/// Quantizes 0-1 floats to 0-`max` with rounding and clamping.
+///
+/// Without dithering every sample is rounded to the nearest level, ordered and blue
+/// noise dithering move the rounding threshold per pixel so smooth gradients do not
+/// band. All channels of a pixel share the threshold.
+pub(crate) fn quantize<T, O>(
+    values: ArrayViewD<T>,
+    max: f64,
+    dither: TypeDither,
+    to: impl Fn(f64) -> O,
+) -> Vec<O>
+where
+    T: Copy + Into<f64>,
+{
+    let shape = values.shape();
+    let width = shape.get(1).copied().unwrap_or(1).max(1);
+    let channels = shape.get(2).copied().unwrap_or(1).max(1);
+    let threshold = |i: usize| {
+        let pixel = i / channels;
+        let (x, y) = (pixel % width, pixel / width);
+        match dither {
+            TypeDither::NONE => 0.5,
+            TypeDither::ORDERED => (bayer(x, y) as f64 + 0.5) / (BAYER_SIZE * BAYER_SIZE) as f64,
+            TypeDither::BLUENOISE => {
+                let i = (y % BLUE_NOISE_SIZE) * BLUE_NOISE_SIZE + x % BLUE_NOISE_SIZE;
+                (blue_noise()[i] as f64 + 0.5) / (BLUE_NOISE_SIZE * BLUE_NOISE_SIZE) as f64
+            }
+        }
+    };
+    values
+        .iter()
+        .enumerate()
+        .map(|(i, &value)| {
+            let value = (value.into() * max + threshold(i)).floor();
+            // NaN ends up as 0 through the saturating cast
+            to(value.clamp(0.0, max))
+        })
+        .collect()
+}
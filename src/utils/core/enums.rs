@@ -46,12 +46,15 @@ pub enum ImgColor {
     GRAY = 0,
     RGB = 1,
     DYNAMIC = 2,
+    RGBA = 3,
+    GRAYA = 4,
 }
 #[pyclass]
 #[derive(Clone, Copy)]
 pub enum ImgFormat {
     U8 = 0,
     F32 = 1,
+    U16 = 2,
 }
 
 #[pyclass]
@@ -66,3 +69,40 @@ pub enum ResizeFilters {
     Gaussian,
     Lanczos3,
 }
+
+#[pyclass]
+#[derive(Clone, Copy)]
+pub enum SaveFormat {
+    PNG = 0,
+    JPEG = 1,
+    WEBP = 2,
+    TIFF = 3,
+    BMP = 4,
+}
+
+#[pyclass]
+#[derive(Clone, Copy)]
+pub enum ChromaSubsampling {
+    YUV444 = 0,
+    YUV422 = 1,
+    YUV420 = 2,
+}
+
+#[pyclass]
+#[derive(Clone, Copy)]
+pub enum PngFilter {
+    NoFilter = 0,
+    Sub = 1,
+    Up = 2,
+    Avg = 3,
+    Paeth = 4,
+    Adaptive = 5,
+}
+
+#[pyclass]
+#[derive(Clone, Copy)]
+pub enum TypeDither {
+    NONE = 0,
+    ORDERED = 1,
+    BLUENOISE = 2,
+}
@@ -1,8 +1,9 @@
 use std::cmp::{max, min};
 
-use ndarray::{s, Array2, Array3};
+use ndarray::{concatenate, s, Array2, Array3, ArrayD, ArrayView2, ArrayViewD, Axis, IxDyn, Slice};
 use noise::{NoiseFn, OpenSimplex, Perlin, PerlinSurflet, Simplex, SuperSimplex};
 use numpy::{PyArrayDyn, PyReadonlyArray2, PyReadonlyArrayDyn, ToPyArray};
+use pyo3::exceptions::PyValueError;
 use pyo3::{pyfunction, Py, PyResult, Python};
 use rand::Rng;
 
@@ -40,27 +41,30 @@ pub fn noise_generate<'py>(
     py: Python,
 ) -> PyResult<Py<PyArrayDyn<f32>>> {
     let seed = seed.unwrap_or(rand::thread_rng().gen_range(1..=10000) as u32);
-    match size.len() {
-        2 => {
+    let array = match size.len() {
+        2 => py.allow_threads(|| {
             let mut array: Array2<f32> = Array2::zeros((size[0], size[1]));
             let type_fn = generate_noise2d(type_noise, seed);
             for ((x, y), value) in array.indexed_iter_mut() {
                 *value = noise_2d(&type_fn, x, y, octaves, frequency, lacunarity);
             }
-            Ok(array.into_dyn().to_pyarray(py).into())
-        }
-        3 => {
+            array.into_dyn()
+        }),
+        3 => py.allow_threads(|| {
             let mut array: Array3<f32> = Array3::zeros((size[0], size[1], size[2]));
             let type_fn = generate_noise3d(type_noise, seed);
             for ((x, y, z), value) in array.indexed_iter_mut() {
                 *value = noise_3d(&type_fn, x, y, z, octaves, frequency, lacunarity);
             }
-            Ok(array.into_dyn().to_pyarray(py).into())
+            array.into_dyn()
+        }),
+        _ => {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "Unsupported dimensions",
+            ))
         }
-        _ => Err(pyo3::exceptions::PyValueError::new_err(
-            "Unsupported dimensions",
-        )),
-    }
+    };
+    Ok(array.to_pyarray(py).into())
 }
 
 fn crop_cord_2d(img: &ndarray::ArrayD<f32>) -> (usize, usize, usize, usize) {
@@ -115,6 +119,264 @@ pub fn crop_cord(input: PyReadonlyArrayDyn<f32>) -> PyResult<(usize, usize, usiz
     }
 }
 
+fn uniform_rows_2d(img: &ArrayViewD<f32>, tolerance: f32) -> Vec<bool> {
+    let shap = img.shape();
+    let mut uniform = vec![true; shap[0]];
+    for x in 0..shap[0] {
+        for y in 1..shap[1] {
+            if (img[[x, y]] - img[[x, 0]]).abs() > tolerance {
+                uniform[x] = false;
+                break;
+            }
+        }
+    }
+    uniform
+}
+
+fn uniform_rows_3d(img: &ArrayViewD<f32>, tolerance: f32) -> Vec<bool> {
+    let shap = img.shape();
+    let mut uniform = vec![true; shap[0]];
+    'rows: for x in 0..shap[0] {
+        for y in 1..shap[1] {
+            for c in 0..shap[2] {
+                if (img[[x, y, c]] - img[[x, 0, c]]).abs() > tolerance {
+                    uniform[x] = false;
+                    continue 'rows;
+                }
+            }
+        }
+    }
+    uniform
+}
+
+/// Picks the rows where the pages of a strip start, so that every page but the last is
+/// `min_height` to `max_height` rows tall. A page is cut in the middle of the longest run
+/// of uniform rows inside that range, at `max_height` when the range has none.
+fn split_cuts(uniform: &[bool], min_height: usize, max_height: usize) -> Vec<usize> {
+    let height = uniform.len();
+    let mut cuts = Vec::new();
+    let mut start = 0;
+    while height - start > max_height {
+        let first = start + min_height;
+        let mut cut = start + max_height;
+        let mut best = 0;
+        let mut run = 0usize;
+        // a content row past the range closes the last run
+        let window = uniform[first..=start + max_height].iter().chain([&false]);
+        for (y, &row) in window.enumerate() {
+            if row {
+                run += 1;
+                continue;
+            }
+            if run > best {
+                best = run;
+                cut = first + y - run.div_ceil(2);
+            }
+            run = 0;
+        }
+        cuts.push(cut);
+        start = cut;
+    }
+    cuts
+}
+
+#[pyfunction]
+#[pyo3(signature = (input, min_height, max_height, tolerance=None))]
+/// Finds where to cut a long webtoon strip into pages without slicing through panels or
+/// speech bubbles: cuts are placed on rows of one color (the gutters).
+///
+/// # Arguments
+/// * `input` - 2D or 3D image array (height, width[, channels]), f32.
+/// * `min_height` - Minimum page height in rows.
+/// * `max_height` - Maximum page height in rows.
+/// * `tolerance` - Maximum difference to the first pixel of a row for it to count as uniform, 0.0 by default.
+///
+/// # Returns
+/// * `Vec<usize>` - Rows where the pages after the first start, the last page can be shorter than `min_height`.
+pub fn split_webtoon(
+    input: PyReadonlyArrayDyn<f32>,
+    min_height: usize,
+    max_height: usize,
+    tolerance: Option<f32>,
+    py: Python,
+) -> PyResult<Vec<usize>> {
+    if min_height == 0 || min_height > max_height {
+        return Err(PyValueError::new_err(
+            "min_height must be between 1 and max_height",
+        ));
+    }
+    let tolerance = tolerance.unwrap_or(0.0);
+    let array = input.as_array().to_owned();
+    let uniform = match array.shape().len() {
+        2 => py.allow_threads(|| uniform_rows_2d(&array.view(), tolerance)),
+        3 => py.allow_threads(|| uniform_rows_3d(&array.view(), tolerance)),
+        _ => return Err(PyValueError::new_err("Unsupported dimensions")),
+    };
+    Ok(split_cuts(&uniform, min_height, max_height))
+}
+
+#[pyfunction]
+/// Stitches strips back into one tall image, e.g. pages cut by `split_webtoon`.
+///
+/// # Arguments
+/// * `inputs` - Arrays with the same width and channels, top to bottom.
+///
+/// # Returns
+/// * `PyArrayDyn<f32>` - The strips stacked vertically.
+pub fn stitch(inputs: Vec<PyReadonlyArrayDyn<f32>>, py: Python) -> PyResult<Py<PyArrayDyn<f32>>> {
+    let strips: Vec<ArrayD<f32>> = inputs.iter().map(|input| input.as_array().to_owned()).collect();
+    let array = py
+        .allow_threads(|| {
+            let views: Vec<ArrayViewD<f32>> = strips.iter().map(|strip| strip.view()).collect();
+            concatenate(Axis(0), &views)
+        })
+        .map_err(|err| PyValueError::new_err(format!("Strips do not fit together: {}", err)))?;
+    Ok(array.to_pyarray(py).into())
+}
+
+/// Finds the gutter of a spread: the middle of the longest run of uniform columns in the
+/// central tenth of the page, the one nearest the center on ties.
+fn spread_gutter(uniform: &[bool]) -> Option<usize> {
+    let width = uniform.len();
+    let center = width / 2;
+    let half_band = max(width / 20, 1);
+    let first = center.saturating_sub(half_band);
+    let last = min(center + half_band, width - 1);
+    let mut best: Option<(usize, usize)> = None;
+    let mut run = 0usize;
+    // a content column past the band closes the last run
+    let band = uniform[first..=last].iter().chain([&false]);
+    for (x, &column) in band.enumerate() {
+        if column {
+            run += 1;
+            continue;
+        }
+        let middle = first + x - run.div_ceil(2);
+        let better = best.is_none_or(|(len, mid)| {
+            run > len || (run == len && middle.abs_diff(center) < mid.abs_diff(center))
+        });
+        if run > 0 && middle > 0 && better {
+            best = Some((run, middle));
+        }
+        run = 0;
+    }
+    best.map(|(_, middle)| middle)
+}
+
+/// Splits a page into the pages of a spread when it is at least `min_ratio` times wider
+/// than tall and has a gutter (the center without `require_gutter`).
+///
+/// # Returns
+/// Page rectangles in reading order as (x_min, x_max, y_min, y_max) like `crop_cord`,
+/// the whole page when it is not a spread.
+fn spread_pages(
+    img: &ArrayViewD<f32>,
+    rtl: bool,
+    min_ratio: f32,
+    tolerance: f32,
+    require_gutter: bool,
+) -> Vec<(usize, usize, usize, usize)> {
+    let shap = img.shape();
+    let (height, width) = (shap[0], shap[1]);
+    let whole = vec![(0, height - 1, 0, width - 1)];
+    if width < 2 || (width as f32) < height as f32 * min_ratio {
+        return whole;
+    }
+    // columns scanned as the rows of the transposed image
+    let axes: &[usize] = if shap.len() == 3 { &[1, 0, 2] } else { &[1, 0] };
+    let columns = img.view().permuted_axes(IxDyn(axes));
+    let uniform = match shap.len() {
+        3 => uniform_rows_3d(&columns, tolerance),
+        _ => uniform_rows_2d(&columns, tolerance),
+    };
+    let split = match spread_gutter(&uniform) {
+        Some(split) => split,
+        None if !require_gutter => width / 2,
+        None => return whole,
+    };
+    let left = (0, height - 1, 0, split - 1);
+    let right = (0, height - 1, split, width - 1);
+    if rtl {
+        vec![right, left]
+    } else {
+        vec![left, right]
+    }
+}
+
+fn spread_args(
+    shap: &[usize],
+    min_ratio: Option<f32>,
+    tolerance: Option<f32>,
+    require_gutter: Option<bool>,
+) -> PyResult<(f32, f32, bool)> {
+    if !(2..=3).contains(&shap.len()) || shap[0] == 0 || shap[1] == 0 {
+        return Err(PyValueError::new_err("Unsupported dimensions"));
+    }
+    Ok((
+        min_ratio.unwrap_or(1.0),
+        tolerance.unwrap_or(0.0),
+        require_gutter.unwrap_or(true),
+    ))
+}
+
+#[pyfunction]
+#[pyo3(signature = (input, rtl=None, min_ratio=None, tolerance=None, require_gutter=None))]
+/// Detects a double-page spread and returns the rectangles of its pages in reading order.
+///
+/// # Arguments
+/// * `input` - 2D or 3D image array (height, width[, channels]), f32.
+/// * `rtl` - Right to left reading order (manga), the right page comes first, false by default.
+/// * `min_ratio` - Minimum width / height of a spread, 1.0 by default.
+/// * `tolerance` - Maximum difference to the first pixel of a column for it to count as gutter, 0.0 by default.
+/// * `require_gutter` - Without a uniform gutter near the center the page is not a spread, true by default,
+///   with false such pages are split in the middle.
+///
+/// # Returns
+/// * `Vec<(usize, usize, usize, usize)>` - (x_min, x_max, y_min, y_max) like `crop_cord` for the two pages,
+///   or for the whole page when it is not a spread.
+pub fn spread_cord(
+    input: PyReadonlyArrayDyn<f32>,
+    rtl: Option<bool>,
+    min_ratio: Option<f32>,
+    tolerance: Option<f32>,
+    require_gutter: Option<bool>,
+    py: Python,
+) -> PyResult<Vec<(usize, usize, usize, usize)>> {
+    let array = input.as_array().to_owned();
+    let (min_ratio, tolerance, require_gutter) =
+        spread_args(array.shape(), min_ratio, tolerance, require_gutter)?;
+    let rtl = rtl.unwrap_or(false);
+    Ok(py.allow_threads(|| spread_pages(&array.view(), rtl, min_ratio, tolerance, require_gutter)))
+}
+
+#[pyfunction]
+#[pyo3(signature = (input, rtl=None, min_ratio=None, tolerance=None, require_gutter=None))]
+/// Splits a double-page spread into its pages in reading order, see `spread_cord`.
+///
+/// # Returns
+/// * `Vec<PyArrayDyn<f32>>` - The two pages, or the whole page when it is not a spread.
+pub fn split_spread(
+    input: PyReadonlyArrayDyn<f32>,
+    rtl: Option<bool>,
+    min_ratio: Option<f32>,
+    tolerance: Option<f32>,
+    require_gutter: Option<bool>,
+    py: Python,
+) -> PyResult<Vec<Py<PyArrayDyn<f32>>>> {
+    let array = input.as_array().to_owned();
+    let (min_ratio, tolerance, require_gutter) =
+        spread_args(array.shape(), min_ratio, tolerance, require_gutter)?;
+    let rtl = rtl.unwrap_or(false);
+    let pages = py.allow_threads(|| spread_pages(&array.view(), rtl, min_ratio, tolerance, require_gutter));
+    Ok(pages
+        .into_iter()
+        .map(|(_, _, y_min, y_max)| {
+            let page = array.slice_axis(Axis(1), Slice::from(y_min..=y_max));
+            page.to_pyarray(py).into()
+        })
+        .collect())
+}
+
 #[pyfunction]
 /// Finds the top-left corner of the tile with the highest mean Laplacian intensity.
 ///
@@ -124,8 +386,12 @@ pub fn crop_cord(input: PyReadonlyArrayDyn<f32>) -> PyResult<(usize, usize, usiz
 ///
 /// # Returns
 /// * `(usize, usize)` - Coordinates of the top-left corner of the best tile.
-pub fn best_tile(input: PyReadonlyArray2<f32>, tile_size: usize) -> PyResult<(usize, usize)> {
+pub fn best_tile(input: PyReadonlyArray2<f32>, tile_size: usize, py: Python) -> PyResult<(usize, usize)> {
     let laplacian_abs = input.as_array().to_owned();
+    Ok(py.allow_threads(|| best_tile_2d(laplacian_abs.view(), tile_size)))
+}
+
+fn best_tile_2d(laplacian_abs: ArrayView2<f32>, tile_size: usize) -> (usize, usize) {
     let img_shape = laplacian_abs.dim();
     let tile_area = (tile_size * tile_size) as f32;
     
@@ -179,5 +445,43 @@ pub fn best_tile(input: PyReadonlyArray2<f32>, tile_size: usize) -> PyResult<(us
             right = true;
         }
     }
-    Ok((best_tile[1] as usize, best_tile[2] as usize))
+    (best_tile[1] as usize, best_tile[2] as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cuts_on_longest_gutter() {
+        // content everywhere but rows 30..40 and 52..55
+        let uniform: Vec<bool> = (0..100)
+            .map(|y| (30..40).contains(&y) || (52..55).contains(&y))
+            .collect();
+        assert_eq!(split_cuts(&uniform, 20, 60), [35, 95]);
+        // no gutter in range: forced cut at max_height
+        assert_eq!(split_cuts(&[false; 100], 10, 40), [40, 80]);
+        assert!(split_cuts(&uniform, 20, 100).is_empty());
+    }
+
+    #[test]
+    fn spread_split_on_gutter() {
+        // 2 rows x 40 columns: noise with a flat gutter at columns 19..23
+        let img = Array2::from_shape_fn((2, 40), |(x, y)| match y {
+            19..=22 => 1.0,
+            _ => ((x * 7 + y * 13) % 5) as f32,
+        })
+        .into_dyn();
+        let pages = spread_pages(&img.view(), false, 1.0, 0.0, true);
+        assert_eq!(pages, [(0, 1, 0, 20), (0, 1, 21, 39)]);
+        let pages = spread_pages(&img.view(), true, 1.0, 0.0, true);
+        assert_eq!(pages, [(0, 1, 21, 39), (0, 1, 0, 20)]);
+        // too narrow for a spread
+        assert_eq!(spread_pages(&img.view(), false, 30.0, 0.0, true), [(0, 1, 0, 39)]);
+        // no gutter
+        let flat = Array2::from_shape_fn((2, 40), |(x, y)| ((x + y) % 2) as f32).into_dyn();
+        assert_eq!(spread_pages(&flat.view(), false, 1.0, 0.0, true), [(0, 1, 0, 39)]);
+        let halves = spread_pages(&flat.view(), false, 1.0, 0.0, false);
+        assert_eq!(halves, [(0, 1, 0, 19), (0, 1, 20, 39)]);
+    }
 }
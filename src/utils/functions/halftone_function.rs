@@ -36,11 +36,14 @@ pub fn screentone<'py>(
     let angle = angle.unwrap_or(0);
     let mut array = input.as_array().to_owned();
     let dot_type = dot_type.unwrap_or(TypeDot::CIRCLE);
-    if angle != 0 {
-        screentone_rotate_add(&mut array, dot_size, (angle as f32).to_radians(), dot_type);
-    } else {
-        screentone_add(&mut array, dot_size, dot_type);
-    }
+    let array = py.allow_threads(|| {
+        if angle != 0 {
+            screentone_rotate_add(&mut array, dot_size, (angle as f32).to_radians(), dot_type);
+        } else {
+            screentone_add(&mut array, dot_size, dot_type);
+        }
+        array
+    });
 
     Ok(array.to_pyarray(py).into())
 }
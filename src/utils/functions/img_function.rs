@@ -1,76 +1,307 @@
+use std::collections::HashMap;
 use std::path::Path;
 
-use crate::utils::core::enums::{ImgColor, ImgFormat};
-use numpy::{PyReadonlyArrayDyn, ToPyArray};
-use pyo3::exceptions::{PyOSError, PyTypeError};
-use pyo3::{pyfunction, IntoPy, IntoPyObject, IntoPyObjectExt, PyErr, PyObject, PyResult, Python};
+use crate::utils::core::dither::quantize;
+use crate::utils::core::enums::{ImgColor, ImgFormat, SaveFormat, TypeDither};
+use filebuffer::FileBuffer;
+use image::DynamicImage;
+use ndarray::ArrayD;
+use numpy::{Element, PyReadonlyArrayDyn, ToPyArray};
+use pyo3::exceptions::{PyIndexError, PyOSError, PyTypeError, PyValueError};
+use pyo3::types::{PyAnyMethods, PyBytes};
+use pyo3::{
+    pyclass, pyfunction, pymethods, IntoPy, IntoPyObject, IntoPyObjectExt, PyErr, PyObject, PyRef,
+    PyRefMut, PyResult, Python,
+};
 
-use crate::utils::image::decode::{all_read_f32, all_read_u8};
-use crate::utils::image::save::save_img_vec;
-use crate::utils::image::size_decode::path_to_size;
+use crate::utils::image::archive::{
+    archive_pages, archive_read, archive_read_batch, save_cbz as write_cbz,
+};
+use crate::utils::image::decode::{
+    all_read_batch, all_read_f32, all_read_u16, all_read_u8, bytes_read_f32, bytes_read_u16,
+    bytes_read_u8, img_to_f32, img_to_u16, img_to_u8,
+};
+use crate::utils::image::frames::{frame_count, read_frames as decode_frames};
+use crate::utils::image::icc::icc_profile;
+use crate::utils::image::psd::{PsdFile, PsdLayer, PsdLayerKind};
+use crate::utils::image::psd_write::write_psd;
+use crate::utils::image::region::{
+    all_read_region_f32, all_read_region_u16, all_read_region_u8, Region,
+};
+use crate::utils::image::save::{encode_img_vec, save_img_vec, vec_to_img, ImgVec, SaveOptions};
+use crate::utils::image::size_decode::{path_to_info, path_to_size, ImgInfo};
+use crate::utils::image::strips::{Strip, StripReader};
 
 #[pyfunction]
-pub fn save(input: PyObject, out_path: String, py: Python) -> PyResult<()> {
+#[pyo3(signature = (input, out_path, format=None, options=None))]
+pub fn save(
+    input: PyObject,
+    out_path: String,
+    format: Option<SaveFormat>,
+    options: Option<SaveOptions>,
+    py: Python,
+) -> PyResult<()> {
     //function to save an image, currently supports:
     //   f32 0-1 array
     //   f64 0-1 array
     //   u8 0-255 array
+    //   u16 0-65535 array
+    //   floats are rounded and clamped, options.dither adds ordered or blue noise dithering
+    //   shapes (h,w), (h,w,1) gray, (h,w,2) gray+alpha, (h,w,3) rgb, (h,w,4) rgba
+    //format -> SaveFormat, None = inferred from the out_path extension
+    //options -> SaveOptions encoder settings (jpeg quality/subsampling, png compression/filter, webp lossless)
+
+    let options = options.unwrap_or_default();
+    let (vec_img, shape) = array_to_vec(&input, &options, py)?;
+    //saving the finished vector, on the passed path
+    match save_img_vec(vec_img, &shape, Path::new(&out_path), format, &options) {
+        Ok(()) => Ok(()),
+        Err(err) => Err(PyErr::new::<PyOSError, _>(format!(
+            "Error saving image: {}",
+            err
+        ))),
+    }
+}
 
-    let vec_img: Vec<u8>;
+fn array_to_vec(
+    input: &PyObject,
+    options: &SaveOptions,
+    py: Python,
+) -> PyResult<(ImgVec, Vec<usize>)> {
+    let vec_img: ImgVec;
     let shape: Vec<usize>;
+    // floats are quantized to 16 bit only when asked for, u16 arrays keep their samples
+    let float_u16 = options.bit_depth == Some(16);
+    let dither = options.dither.unwrap_or(TypeDither::NONE);
 
     //array extension definition
     if let Ok(array_py) = input.extract::<PyReadonlyArrayDyn<u8>>(py) {
-        let array8 = array_py.as_array().to_owned();
-        vec_img = array8.iter().map(|&x| x).collect();
+        let array8 = array_py.as_array();
+        vec_img = ImgVec::U8(array8.iter().copied().collect());
         shape = array8.shape().to_vec();
+    } else if let Ok(array_py) = input.extract::<PyReadonlyArrayDyn<u16>>(py) {
+        let array16 = array_py.as_array();
+        vec_img = ImgVec::U16(array16.iter().copied().collect());
+        shape = array16.shape().to_vec();
     } else if let Ok(array_py) = input.extract::<PyReadonlyArrayDyn<f32>>(py) {
-        let arr32 = array_py.as_array().to_owned();
-        vec_img = arr32.iter().map(|&x| (x * 255.0) as u8).collect();
+        let arr32 = array_py.as_array();
         shape = arr32.shape().to_vec();
+        vec_img = if float_u16 {
+            ImgVec::U16(quantize(arr32, 65535.0, dither, |x| x as u16))
+        } else {
+            ImgVec::U8(quantize(arr32, 255.0, dither, |x| x as u8))
+        };
     } else if let Ok(array_py) = input.extract::<PyReadonlyArrayDyn<f64>>(py) {
-        let arr64 = array_py.as_array().to_owned();
-        vec_img = arr64.iter().map(|&x| (x * 255.0) as u8).collect();
+        let arr64 = array_py.as_array();
         shape = arr64.shape().to_vec();
+        vec_img = if float_u16 {
+            ImgVec::U16(quantize(arr64, 65535.0, dither, |x| x as u16))
+        } else {
+            ImgVec::U8(quantize(arr64, 255.0, dither, |x| x as u8))
+        };
     } else {
         return Err(PyErr::new::<PyTypeError, _>("Unsupported array type"));
     }
-    //saving the finished vector, on the passed path
-    match save_img_vec(&vec_img, &shape, Path::new(&out_path)) {
-        Ok(()) => Ok(()),
+    Ok((vec_img, shape))
+}
+
+/// Writes pages to a comic archive (cbz) named 001, 002... in page order.
+///
+/// # Arguments
+///
+/// * `pages` - Arrays like `save` takes, encoded in parallel.
+/// * `path` - Path of the cbz file.
+/// * `format` - Image format of the pages, `SaveFormat::PNG` by default.
+/// * `options` - `SaveOptions` of the encoder.
+/// * `comic_info` - ComicInfo.xml fields (`{"Series": ..., "Number": 1}`), no
+///   ComicInfo.xml when `None`.
+///
+/// # Errors
+///
+/// This function will return an error if a page can not be encoded, a ComicInfo
+/// field is unknown or the file can not be written.
+#[pyfunction]
+#[pyo3(signature = (pages, path, format=None, options=None, comic_info=None))]
+pub fn save_cbz(
+    pages: Vec<PyObject>,
+    path: String,
+    format: Option<SaveFormat>,
+    options: Option<SaveOptions>,
+    comic_info: Option<HashMap<String, PyObject>>,
+    py: Python,
+) -> PyResult<()> {
+    let format = format.unwrap_or(SaveFormat::PNG);
+    let options = options.unwrap_or_default();
+    let pages = pages
+        .iter()
+        .map(|page| array_to_vec(page, &options, py))
+        .collect::<PyResult<Vec<_>>>()?;
+    let info = comic_info
+        .map(|fields| {
+            fields
+                .into_iter()
+                .map(|(field, value)| Ok((field, value.bind(py).str()?.to_string())))
+                .collect::<PyResult<Vec<_>>>()
+        })
+        .transpose()?;
+    py.allow_threads(|| {
+        write_cbz(pages, Path::new(&path), format, &options, info.as_deref())
+            .map_err(|err| err.to_string())
+    })
+    .map_err(|err| PyOSError::new_err(format!("Error saving image: {}", err)))
+}
+
+#[pyfunction]
+#[pyo3(signature = (input, format, options=None))]
+pub fn encode(
+    input: PyObject,
+    format: SaveFormat,
+    options: Option<SaveOptions>,
+    py: Python,
+) -> PyResult<PyObject> {
+    // function to encode an image to bytes in memory, e.g. for uploads or archives,
+    // takes the same arrays and options as save
+
+    let options = options.unwrap_or_default();
+    let (vec_img, shape) = array_to_vec(&input, &options, py)?;
+    match encode_img_vec(vec_img, &shape, format, &options) {
+        Ok(bytes) => Ok(PyBytes::new(py, &bytes).into_any().unbind()),
         Err(err) => Err(PyErr::new::<PyOSError, _>(format!(
-            "Error saving image: {}",
+            "Error encoding image: {}",
             err
         ))),
     }
 }
 
+/// Checks the `scale` of a reduced size read, 1 (full size) when not given.
+fn read_scale(scale: Option<u32>) -> PyResult<u32> {
+    match scale.unwrap_or(1) {
+        scale @ (1 | 2 | 4 | 8) => Ok(scale),
+        scale => Err(PyValueError::new_err(format!(
+            "scale must be 1, 2, 4 or 8, got {}",
+            scale
+        ))),
+    }
+}
+
 #[pyfunction]
+#[pyo3(signature = (path, mode=None, format=None, apply_orientation=None, to_srgb=None, scale=None))]
 pub fn read(
     path: String,
     mode: Option<ImgColor>,
     format: Option<ImgFormat>,
+    apply_orientation: Option<bool>,
+    to_srgb: Option<bool>,
+    scale: Option<u32>,
     py: Python,
 ) -> PyResult<PyObject> {
     // The function to read the image.
     // Input parameters:
     //      path -> str file path
-    //      mode -> uint 0 -> gray 1-> rgb 2-> psd dynamic format, and in other cases rgb, None = 2
-    //      format -> uint 0 -> f32 0-1 img, 1+ -> u8 0-255, None = 1
+    //      mode -> uint 0 -> gray 1-> rgb 2-> dynamic (native channel count) 3 -> rgba 4 -> gray+alpha, None = 2
+    //      format -> uint 0 -> u8 0-255, 1 -> f32 0-1 img, 2 -> u16 0-65535, None = 0
+    //      f32 and u16 are decoded from the source bit depth (16 bit png/tiff/psd, 32 bit float tiff/psd)
+    //      cmyk/lab/multichannel psd and cmyk jpeg are converted to rgb (cmyk through the embedded icc
+    //      profile), dynamic returns their native channels instead (cmyk as ink 0 = no ink, lab as stored)
+    //      apply_orientation -> bool rotate/flip the image upright by its exif orientation, None = False
+    //      to_srgb -> bool convert rgb/gray pixels from the embedded icc profile to sRGB, None = False
+    //      scale -> uint 2, 4 or 8 reads at 1/scale of the size for previews (baseline jpeg is decoded
+    //      at that size, other formats are box resized after decoding), None = 1
 
     let path = Path::new(&path);
     let mode = mode.unwrap_or(ImgColor::DYNAMIC);
     let format = format.unwrap_or(ImgFormat::U8);
+    let apply_orientation = apply_orientation.unwrap_or(false);
+    let to_srgb = to_srgb.unwrap_or(false);
+    let scale = read_scale(scale)?;
 
     match format {
-        ImgFormat::F32 => match all_read_f32(path, mode) {
+        ImgFormat::F32 => match py.allow_threads(|| {
+            all_read_f32(path, mode, apply_orientation, to_srgb, scale)
+                .map_err(|err| err.to_string())
+        }) {
+            Ok(array) => Ok(array.to_pyarray(py).into_py(py)),
+            Err(err) => Err(PyErr::new::<PyOSError, _>(format!(
+                "Error reading file: {}",
+                err
+            ))),
+        },
+        ImgFormat::U8 => match py.allow_threads(|| {
+            all_read_u8(path, mode, apply_orientation, to_srgb, scale)
+                .map_err(|err| err.to_string())
+        }) {
+            Ok(array) => Ok(array.to_pyarray(py).into_py(py)),
+            Err(err) => Err(PyErr::new::<PyOSError, _>(format!(
+                "Error reading file: {}",
+                err
+            ))),
+        },
+        ImgFormat::U16 => match py.allow_threads(|| {
+            all_read_u16(path, mode, apply_orientation, to_srgb, scale)
+                .map_err(|err| err.to_string())
+        }) {
+            Ok(array) => Ok(array.to_pyarray(py).into_py(py)),
+            Err(err) => Err(PyErr::new::<PyOSError, _>(format!(
+                "Error reading file: {}",
+                err
+            ))),
+        },
+    }
+}
+#[pyfunction]
+#[pyo3(signature = (path, x, y, w, h, mode=None, format=None))]
+#[allow(clippy::too_many_arguments)]
+pub fn read_region(
+    path: String,
+    x: usize,
+    y: usize,
+    w: usize,
+    h: usize,
+    mode: Option<ImgColor>,
+    format: Option<ImgFormat>,
+    py: Python,
+) -> PyResult<PyObject> {
+    // The function to read a rectangle of an image, e.g. a strip of a very tall webtoon page.
+    // Input parameters:
+    //      path -> str file path
+    //      x, y -> uint top left corner in stored pixels (exif orientation is not applied)
+    //      w, h -> uint size of the rectangle, it has to lie inside the image
+    //      mode, format -> same as in read
+    //      psd and png only decode the rows down to the rectangle, tiff only its tiles/strips,
+    //      other formats are decoded in full and cropped
+
+    let path = Path::new(&path);
+    let region = Region {
+        x,
+        y,
+        width: w,
+        height: h,
+    };
+    let mode = mode.unwrap_or(ImgColor::DYNAMIC);
+    let format = format.unwrap_or(ImgFormat::U8);
+
+    match format {
+        ImgFormat::F32 => match py.allow_threads(|| {
+            all_read_region_f32(path, region, mode).map_err(|err| err.to_string())
+        }) {
+            Ok(array) => Ok(array.to_pyarray(py).into_py(py)),
+            Err(err) => Err(PyErr::new::<PyOSError, _>(format!(
+                "Error reading file: {}",
+                err
+            ))),
+        },
+        ImgFormat::U8 => match py
+            .allow_threads(|| all_read_region_u8(path, region, mode).map_err(|err| err.to_string()))
+        {
             Ok(array) => Ok(array.to_pyarray(py).into_py(py)),
             Err(err) => Err(PyErr::new::<PyOSError, _>(format!(
                 "Error reading file: {}",
                 err
             ))),
         },
-        ImgFormat::U8 => match all_read_u8(path, mode) {
+        ImgFormat::U16 => match py.allow_threads(|| {
+            all_read_region_u16(path, region, mode).map_err(|err| err.to_string())
+        }) {
             Ok(array) => Ok(array.to_pyarray(py).into_py(py)),
             Err(err) => Err(PyErr::new::<PyOSError, _>(format!(
                 "Error reading file: {}",
@@ -79,6 +310,420 @@ pub fn read(
         },
     }
 }
+#[pyfunction]
+#[pyo3(signature = (data, mode=None, format=None, apply_orientation=None, to_srgb=None, scale=None))]
+pub fn read_bytes(
+    data: &[u8],
+    mode: Option<ImgColor>,
+    format: Option<ImgFormat>,
+    apply_orientation: Option<bool>,
+    to_srgb: Option<bool>,
+    scale: Option<u32>,
+    py: Python,
+) -> PyResult<PyObject> {
+    // The function to decode an image from memory, e.g. a page pulled out of an archive.
+    // Input parameters:
+    //      data -> bytes encoded image (psd or any format supported by the image crate)
+    //      mode, format, apply_orientation, to_srgb, scale -> same as in read
+
+    let mode = mode.unwrap_or(ImgColor::DYNAMIC);
+    let format = format.unwrap_or(ImgFormat::U8);
+    let apply_orientation = apply_orientation.unwrap_or(false);
+    let to_srgb = to_srgb.unwrap_or(false);
+    let scale = read_scale(scale)?;
+
+    match format {
+        ImgFormat::F32 => match py.allow_threads(|| {
+            bytes_read_f32(data, mode, apply_orientation, to_srgb, scale)
+                .map_err(|err| err.to_string())
+        }) {
+            Ok(array) => Ok(array.to_pyarray(py).into_py(py)),
+            Err(err) => Err(PyErr::new::<PyOSError, _>(format!(
+                "Error decoding bytes: {}",
+                err
+            ))),
+        },
+        ImgFormat::U8 => match py.allow_threads(|| {
+            bytes_read_u8(data, mode, apply_orientation, to_srgb, scale)
+                .map_err(|err| err.to_string())
+        }) {
+            Ok(array) => Ok(array.to_pyarray(py).into_py(py)),
+            Err(err) => Err(PyErr::new::<PyOSError, _>(format!(
+                "Error decoding bytes: {}",
+                err
+            ))),
+        },
+        ImgFormat::U16 => match py.allow_threads(|| {
+            bytes_read_u16(data, mode, apply_orientation, to_srgb, scale)
+                .map_err(|err| err.to_string())
+        }) {
+            Ok(array) => Ok(array.to_pyarray(py).into_py(py)),
+            Err(err) => Err(PyErr::new::<PyOSError, _>(format!(
+                "Error decoding bytes: {}",
+                err
+            ))),
+        },
+    }
+}
+
+/// Reads a list of images in parallel on a rust thread pool, the GIL is released while decoding.
+///
+/// # Arguments
+///
+/// * `paths` - Paths to the image files.
+/// * `mode` - Color mode, same as in `read`, `ImgColor::DYNAMIC` by default.
+/// * `format` - Sample format, same as in `read`, `ImgFormat::U8` by default.
+/// * `threads` - Number of worker threads, one per core by default.
+/// * `apply_orientation` - Rotate/flip the images upright by their exif orientation, same as in `read`.
+/// * `to_srgb` - Convert the pixels from the embedded icc profile to sRGB, same as in `read`.
+/// * `scale` - Read at 1/2, 1/4 or 1/8 of the size, same as in `read`.
+///
+/// # Returns
+///
+/// A tuple of two lists in the order of `paths`: the decoded arrays (`None` for files
+/// that failed) and the error messages (`None` for files that were read).
+///
+/// # Errors
+///
+/// Errors of single files do not raise, only a failure to start the thread pool does.
+#[pyfunction]
+#[pyo3(signature = (paths, mode=None, format=None, threads=None, apply_orientation=None, to_srgb=None, scale=None))]
+#[allow(clippy::too_many_arguments)]
+pub fn read_batch(
+    paths: Vec<String>,
+    mode: Option<ImgColor>,
+    format: Option<ImgFormat>,
+    threads: Option<usize>,
+    apply_orientation: Option<bool>,
+    to_srgb: Option<bool>,
+    scale: Option<u32>,
+    py: Python,
+) -> PyResult<PyBatch> {
+    let mode = mode.unwrap_or(ImgColor::DYNAMIC);
+    let format = format.unwrap_or(ImgFormat::U8);
+    let threads = threads.unwrap_or(0);
+    let apply_orientation = apply_orientation.unwrap_or(false);
+    let to_srgb = to_srgb.unwrap_or(false);
+    let scale = read_scale(scale)?;
+    let pool_err = |err| PyOSError::new_err(format!("Error starting thread pool: {}", err));
+
+    match format {
+        ImgFormat::U8 => {
+            let results = py
+                .allow_threads(|| {
+                    all_read_batch(&paths, threads, |path| {
+                        all_read_u8(path, mode, apply_orientation, to_srgb, scale)
+                    })
+                })
+                .map_err(pool_err)?;
+            Ok(batch_to_py(results, py))
+        }
+        ImgFormat::F32 => {
+            let results = py
+                .allow_threads(|| {
+                    all_read_batch(&paths, threads, |path| {
+                        all_read_f32(path, mode, apply_orientation, to_srgb, scale)
+                    })
+                })
+                .map_err(pool_err)?;
+            Ok(batch_to_py(results, py))
+        }
+        ImgFormat::U16 => {
+            let results = py
+                .allow_threads(|| {
+                    all_read_batch(&paths, threads, |path| {
+                        all_read_u16(path, mode, apply_orientation, to_srgb, scale)
+                    })
+                })
+                .map_err(pool_err)?;
+            Ok(batch_to_py(results, py))
+        }
+    }
+}
+
+/// Reads the embedded icc profile of an image (png, jpeg, tiff, webp, psd).
+///
+/// # Returns
+///
+/// The profile bytes, `None` when the image has no profile.
+///
+/// # Errors
+///
+/// This function will return an error if the file can not be read or its header is invalid.
+#[pyfunction]
+pub fn read_icc(path: String, py: Python) -> PyResult<Option<PyObject>> {
+    let file = FileBuffer::open(Path::new(&path))
+        .map_err(|err| PyOSError::new_err(format!("Error reading file: {}", err)))?;
+    let icc = icc_profile(&file)
+        .map_err(|err| PyOSError::new_err(format!("Error reading file: {}", err)))?;
+    Ok(icc.map(|icc| PyBytes::new(py, &icc).into_any().unbind()))
+}
+
+/// Reads the number of frames of an image: pages of a multi-page tiff, frames of an
+/// animated gif, webp or png, 1 for other images. The pixels are not decoded.
+///
+/// # Errors
+///
+/// This function will return an error if the file can not be read or its header is invalid.
+#[pyfunction]
+pub fn read_frame_count(path: String) -> PyResult<usize> {
+    let file = FileBuffer::open(Path::new(&path))
+        .map_err(|err| PyOSError::new_err(format!("Error reading file: {}", err)))?;
+    frame_count(&file).map_err(|err| PyOSError::new_err(format!("Error reading file: {}", err)))
+}
+
+/// Decodes one frame or page of an image, frame 0 of a still image is the image.
+///
+/// # Arguments
+///
+/// * `path` - Path to the image.
+/// * `index` - Frame index, see `read_frame_count`.
+/// * `mode` - Color mode, `ImgColor::DYNAMIC` by default.
+/// * `format` - Sample format, `ImgFormat::U8` by default.
+///
+/// # Errors
+///
+/// This function will return an error if the frame does not exist or can not be decoded.
+#[pyfunction]
+#[pyo3(signature = (path, index, mode=None, format=None))]
+pub fn read_frame(
+    path: String,
+    index: usize,
+    mode: Option<ImgColor>,
+    format: Option<ImgFormat>,
+    py: Python,
+) -> PyResult<PyObject> {
+    let (mut arrays, _) = frames_to_py(path, Some(index), mode, format, py)?;
+    Ok(arrays.remove(0))
+}
+
+/// Decodes all frames or pages of an image.
+///
+/// # Arguments
+///
+/// * `path` - Path to the image.
+/// * `mode` - Color mode, `ImgColor::DYNAMIC` by default.
+/// * `format` - Sample format, `ImgFormat::U8` by default.
+///
+/// # Returns
+///
+/// A tuple `(arrays, delays)` with the display time of every frame in milliseconds,
+/// 0 for tiff pages and still images.
+///
+/// # Errors
+///
+/// This function will return an error if the file can not be read or a frame can not be decoded.
+#[pyfunction]
+#[pyo3(signature = (path, mode=None, format=None))]
+pub fn read_frames(
+    path: String,
+    mode: Option<ImgColor>,
+    format: Option<ImgFormat>,
+    py: Python,
+) -> PyResult<(Vec<PyObject>, Vec<u32>)> {
+    frames_to_py(path, None, mode, format, py)
+}
+
+fn frames_to_py(
+    path: String,
+    index: Option<usize>,
+    mode: Option<ImgColor>,
+    format: Option<ImgFormat>,
+    py: Python,
+) -> PyResult<(Vec<PyObject>, Vec<u32>)> {
+    let mode = mode.unwrap_or(ImgColor::DYNAMIC);
+    let format = format.unwrap_or(ImgFormat::U8);
+    let frames = py
+        .allow_threads(|| {
+            let file = FileBuffer::open(Path::new(&path)).map_err(|err| err.to_string())?;
+            decode_frames(&file, index).map_err(|err| err.to_string())
+        })
+        .map_err(|err| PyOSError::new_err(format!("Error reading file: {}", err)))?;
+    Ok(frames
+        .into_iter()
+        .map(|frame| {
+            let delay = frame.delay;
+            let array = img_to_pyarray(frame.image, frame.channels, mode, format, py);
+            (array, delay)
+        })
+        .unzip())
+}
+
+/// Lists the pages of a comic archive (cbz/zip): its image entries in natural sort order.
+///
+/// # Errors
+///
+/// This function will return an error if the file can not be read or is not a zip archive.
+#[pyfunction]
+pub fn read_cbz_pages(path: String) -> PyResult<Vec<String>> {
+    let file = FileBuffer::open(Path::new(&path))
+        .map_err(|err| PyOSError::new_err(format!("Error reading file: {}", err)))?;
+    archive_pages(&file).map_err(|err| PyOSError::new_err(format!("Error reading file: {}", err)))
+}
+
+/// Decodes one page of a comic archive without extracting it, like `read`.
+///
+/// # Arguments
+///
+/// * `path` - Path to the cbz/zip file.
+/// * `page` - Index into `read_cbz_pages`.
+/// * `mode` - Color mode, `ImgColor::DYNAMIC` by default.
+/// * `format` - Sample format, `ImgFormat::U8` by default.
+/// * `apply_orientation` - Rotate/flip the page upright by its exif orientation, same as in `read`.
+/// * `to_srgb` - Convert the pixels from the embedded icc profile to sRGB, same as in `read`.
+/// * `scale` - Read at 1/2, 1/4 or 1/8 of the size, same as in `read`.
+///
+/// # Errors
+///
+/// This function will return an error if the page does not exist or can not be decoded.
+#[pyfunction]
+#[pyo3(signature = (path, page, mode=None, format=None, apply_orientation=None, to_srgb=None, scale=None))]
+#[allow(clippy::too_many_arguments)]
+pub fn read_cbz(
+    path: String,
+    page: usize,
+    mode: Option<ImgColor>,
+    format: Option<ImgFormat>,
+    apply_orientation: Option<bool>,
+    to_srgb: Option<bool>,
+    scale: Option<u32>,
+    py: Python,
+) -> PyResult<PyObject> {
+    let mode = mode.unwrap_or(ImgColor::DYNAMIC);
+    let format = format.unwrap_or(ImgFormat::U8);
+    let apply_orientation = apply_orientation.unwrap_or(false);
+    let to_srgb = to_srgb.unwrap_or(false);
+    let scale = read_scale(scale)?;
+    let read_err = |err| PyOSError::new_err(format!("Error reading file: {}", err));
+    let file = FileBuffer::open(Path::new(&path)).map_err(|err| read_err(err.to_string()))?;
+    let pages = archive_pages(&file).map_err(|err| read_err(err.to_string()))?;
+    let name = pages.get(page).ok_or_else(|| {
+        PyIndexError::new_err(format!("page {} out of range, {} pages", page, pages.len()))
+    })?;
+
+    match format {
+        ImgFormat::U8 => py
+            .allow_threads(|| {
+                archive_read(&file, name, |img| {
+                    bytes_read_u8(img, mode, apply_orientation, to_srgb, scale)
+                })
+                .map_err(|err| err.to_string())
+            })
+            .map(|array| array.to_pyarray(py).into_py(py))
+            .map_err(read_err),
+        ImgFormat::F32 => py
+            .allow_threads(|| {
+                archive_read(&file, name, |img| {
+                    bytes_read_f32(img, mode, apply_orientation, to_srgb, scale)
+                })
+                .map_err(|err| err.to_string())
+            })
+            .map(|array| array.to_pyarray(py).into_py(py))
+            .map_err(read_err),
+        ImgFormat::U16 => py
+            .allow_threads(|| {
+                archive_read(&file, name, |img| {
+                    bytes_read_u16(img, mode, apply_orientation, to_srgb, scale)
+                })
+                .map_err(|err| err.to_string())
+            })
+            .map(|array| array.to_pyarray(py).into_py(py))
+            .map_err(read_err),
+    }
+}
+
+/// Decodes all pages of a comic archive in parallel, like `read_batch`.
+///
+/// # Arguments
+///
+/// * `path` - Path to the cbz/zip file.
+/// * `mode` - Color mode, `ImgColor::DYNAMIC` by default.
+/// * `format` - Sample format, `ImgFormat::U8` by default.
+/// * `threads` - Number of worker threads, `None` or 0 uses one per core.
+/// * `apply_orientation` - Rotate/flip the pages upright by their exif orientation, same as in `read`.
+/// * `to_srgb` - Convert the pixels from the embedded icc profile to sRGB, same as in `read`.
+/// * `scale` - Read at 1/2, 1/4 or 1/8 of the size, same as in `read`.
+///
+/// # Returns
+///
+/// A tuple `(arrays, errors)` in page order, see `read_batch`.
+///
+/// # Errors
+///
+/// Errors of single pages do not raise, only a failure to open the archive or
+/// start the thread pool does.
+#[pyfunction]
+#[pyo3(signature = (path, mode=None, format=None, threads=None, apply_orientation=None, to_srgb=None, scale=None))]
+#[allow(clippy::too_many_arguments)]
+pub fn read_cbz_batch(
+    path: String,
+    mode: Option<ImgColor>,
+    format: Option<ImgFormat>,
+    threads: Option<usize>,
+    apply_orientation: Option<bool>,
+    to_srgb: Option<bool>,
+    scale: Option<u32>,
+    py: Python,
+) -> PyResult<PyBatch> {
+    let mode = mode.unwrap_or(ImgColor::DYNAMIC);
+    let format = format.unwrap_or(ImgFormat::U8);
+    let threads = threads.unwrap_or(0);
+    let apply_orientation = apply_orientation.unwrap_or(false);
+    let to_srgb = to_srgb.unwrap_or(false);
+    let scale = read_scale(scale)?;
+    let file = FileBuffer::open(Path::new(&path))
+        .map_err(|err| PyOSError::new_err(format!("Error reading file: {}", err)))?;
+    let pages = archive_pages(&file)
+        .map_err(|err| PyOSError::new_err(format!("Error reading file: {}", err)))?;
+    let pool_err = |err| PyOSError::new_err(format!("Error starting thread pool: {}", err));
+
+    match format {
+        ImgFormat::U8 => {
+            let results = py
+                .allow_threads(|| {
+                    archive_read_batch(&file, &pages, threads, |img| {
+                        bytes_read_u8(img, mode, apply_orientation, to_srgb, scale)
+                    })
+                })
+                .map_err(pool_err)?;
+            Ok(batch_to_py(results, py))
+        }
+        ImgFormat::F32 => {
+            let results = py
+                .allow_threads(|| {
+                    archive_read_batch(&file, &pages, threads, |img| {
+                        bytes_read_f32(img, mode, apply_orientation, to_srgb, scale)
+                    })
+                })
+                .map_err(pool_err)?;
+            Ok(batch_to_py(results, py))
+        }
+        ImgFormat::U16 => {
+            let results = py
+                .allow_threads(|| {
+                    archive_read_batch(&file, &pages, threads, |img| {
+                        bytes_read_u16(img, mode, apply_orientation, to_srgb, scale)
+                    })
+                })
+                .map_err(pool_err)?;
+            Ok(batch_to_py(results, py))
+        }
+    }
+}
+
+/// Decoded arrays and error messages of a batch, both in input order.
+type PyBatch = (Vec<Option<PyObject>>, Vec<Option<String>>);
+
+fn batch_to_py<T: Element>(results: Vec<Result<ArrayD<T>, String>>, py: Python) -> PyBatch {
+    results
+        .into_iter()
+        .map(|result| match result {
+            Ok(array) => (Some(array.to_pyarray(py).into_py(py)), None),
+            Err(err) => (None, Some(format!("Error reading file: {}", err))),
+        })
+        .unzip()
+}
+
 /// Reads the dimensions (width and height) of the image at the given path.
 ///
 /// # Arguments
@@ -104,3 +749,282 @@ pub fn read(
 pub fn read_size(path: String) -> PyResult<(u32, u32)> {
     path_to_size(Path::new(&path))
 }
+
+/// Reads the image properties from the file headers without decoding the pixels.
+///
+/// # Arguments
+///
+/// * `path` - A string slice that holds the path to the image file.
+///
+/// # Returns
+///
+/// An `ImgInfo` with the width, height, channel count, bit depth, color mode
+/// (including psd cmyk, lab and indexed modes), format name, dpi, whether an icc
+/// profile is embedded and the exif orientation.
+///
+/// # Errors
+///
+/// This function will return an error if the file does not exist or the headers
+/// can not be parsed.
+#[pyfunction]
+pub fn read_info(path: String) -> PyResult<ImgInfo> {
+    path_to_info(Path::new(&path))
+}
+
+fn img_to_pyarray(
+    img: DynamicImage,
+    native: u8,
+    mode: ImgColor,
+    format: ImgFormat,
+    py: Python,
+) -> PyObject {
+    match format {
+        ImgFormat::U8 => img_to_u8(img, native, mode).to_pyarray(py).into_py(py),
+        ImgFormat::F32 => img_to_f32(img, native, mode).to_pyarray(py).into_py(py),
+        ImgFormat::U16 => img_to_u16(img, native, mode).to_pyarray(py).into_py(py),
+    }
+}
+
+/// Opens an image for reading in consecutive horizontal strips with bounded memory,
+/// e.g. to run `fast_color_level`, `screentone` or `resize_img` on very tall webtoon pages.
+///
+/// # Arguments
+///
+/// * `path` - Path to the image, non interlaced png, psd or baseline jpeg.
+/// * `height` - Rows per strip, the last strip can be shorter.
+/// * `overlap` - Extra rows of the neighbouring strips above and below every strip, 0 by default.
+/// * `mode` - Color mode, `ImgColor::DYNAMIC` by default.
+/// * `format` - Sample format, `ImgFormat::U8` by default.
+///
+/// # Errors
+///
+/// This function will return an error if the file can not be opened or decoded, or
+/// if its format can not be read in strips.
+#[pyfunction]
+#[pyo3(signature = (path, height, overlap=None, mode=None, format=None))]
+pub fn read_strips(
+    path: String,
+    height: usize,
+    overlap: Option<usize>,
+    mode: Option<ImgColor>,
+    format: Option<ImgFormat>,
+    py: Python,
+) -> PyResult<PyStripReader> {
+    let mode = mode.unwrap_or(ImgColor::DYNAMIC);
+    let format = format.unwrap_or(ImgFormat::U8);
+    let native = matches!(mode, ImgColor::DYNAMIC);
+    let reader = py
+        .allow_threads(|| {
+            StripReader::open(Path::new(&path), height, overlap.unwrap_or(0), native)
+                .map_err(|err| err.to_string())
+        })
+        .map_err(|err| PyOSError::new_err(format!("Error reading file: {}", err)))?;
+    Ok(PyStripReader {
+        width: reader.width,
+        height: reader.height,
+        reader,
+        mode,
+        format,
+    })
+}
+
+/// Iterator over the strips of an image, yields `(y, strip)` with `y` the first image
+/// row of the strip (overlap included).
+#[pyclass(name = "StripReader")]
+pub struct PyStripReader {
+    #[pyo3(get)]
+    width: usize,
+    #[pyo3(get)]
+    height: usize,
+    reader: StripReader,
+    mode: ImgColor,
+    format: ImgFormat,
+}
+
+#[pymethods]
+impl PyStripReader {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<'_, Self>, py: Python) -> PyResult<Option<(usize, PyObject)>> {
+        let (mode, format) = (slf.mode, slf.format);
+        let reader = &mut slf.reader;
+        let strip = py
+            .allow_threads(|| reader.next_strip().map_err(|err| err.to_string()))
+            .map_err(|err| PyOSError::new_err(format!("Error reading file: {}", err)))?;
+        let Some((y, strip)) = strip else {
+            return Ok(None);
+        };
+        let array = match strip {
+            Strip::Image(img, native) => img_to_pyarray(img, native, mode, format, py),
+            Strip::Native(native) => match format {
+                ImgFormat::U8 => native
+                    .to_array(None, |v| (v * 255.0).round() as u8)
+                    .map(|array| array.to_pyarray(py).into_py(py)),
+                ImgFormat::F32 => native
+                    .to_array(None, |v| v)
+                    .map(|array| array.to_pyarray(py).into_py(py)),
+                ImgFormat::U16 => native
+                    .to_array(None, |v| (v * 65535.0).round() as u16)
+                    .map(|array| array.to_pyarray(py).into_py(py)),
+            }
+            .map_err(|err| PyOSError::new_err(format!("Error reading file: {}", err)))?,
+        };
+        Ok(Some((y, array)))
+    }
+}
+
+/// A single layer of a psd file.
+#[pyclass(name = "PsdLayer")]
+pub struct PyPsdLayer {
+    #[pyo3(get)]
+    name: String,
+    /// "pixel", "text", "group" or "group_end"
+    #[pyo3(get)]
+    kind: String,
+    #[pyo3(get)]
+    visible: bool,
+    /// 0-255
+    #[pyo3(get)]
+    opacity: u8,
+    /// photoshop blend mode key: "norm", "mul", "scrn", "pass"...
+    #[pyo3(get)]
+    blend_mode: String,
+    /// (x, y) of the top-left corner of the layer on the canvas
+    #[pyo3(get)]
+    offset: (i32, i32),
+    #[pyo3(get)]
+    data: PyObject,
+}
+
+#[pymethods]
+impl PyPsdLayer {
+    #[new]
+    #[pyo3(signature = (data=None, name=None, opacity=None, visible=None, blend_mode=None, offset=None, kind=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        data: Option<PyObject>,
+        name: Option<String>,
+        opacity: Option<u8>,
+        visible: Option<bool>,
+        blend_mode: Option<String>,
+        offset: Option<(i32, i32)>,
+        kind: Option<String>,
+        py: Python,
+    ) -> Self {
+        PyPsdLayer {
+            name: name.unwrap_or_else(|| "Layer".to_string()),
+            kind: kind.unwrap_or_else(|| "pixel".to_string()),
+            visible: visible.unwrap_or(true),
+            opacity: opacity.unwrap_or(255),
+            blend_mode: blend_mode.unwrap_or_else(|| "norm".to_string()),
+            offset: offset.unwrap_or((0, 0)),
+            data: data.unwrap_or_else(|| py.None()),
+        }
+    }
+}
+
+/// Reads the layers of a psd file, bottom to top as they are stored in the file.
+///
+/// # Arguments
+///
+/// * `path` - Path to the psd file.
+/// * `mode` - Color mode of the layer pixels, `ImgColor::DYNAMIC` (default) keeps
+///   the document channels plus the layer transparency (cmyk and lab layers are
+///   converted to rgba).
+/// * `format` - Sample format of the layer pixels, `ImgFormat::U8` by default.
+///
+/// # Errors
+///
+/// This function will return an error if the file can not be read or is not a valid psd.
+#[pyfunction]
+#[pyo3(signature = (path, mode=None, format=None))]
+pub fn read_psd_layers(
+    path: String,
+    mode: Option<ImgColor>,
+    format: Option<ImgFormat>,
+    py: Python,
+) -> PyResult<Vec<PyPsdLayer>> {
+    let mode = mode.unwrap_or(ImgColor::DYNAMIC);
+    let format = format.unwrap_or(ImgFormat::U8);
+    let file = FileBuffer::open(Path::new(&path))
+        .map_err(|err| PyOSError::new_err(format!("Error reading file: {}", err)))?;
+    let layers = PsdFile::parse(&file)
+        .and_then(|psd| psd.decode_layers())
+        .map_err(|err| PyOSError::new_err(format!("Error reading psd layers: {}", err)))?;
+    Ok(layers
+        .into_iter()
+        .map(|layer| PyPsdLayer {
+            name: layer.name,
+            kind: layer.kind.name().to_string(),
+            visible: layer.visible,
+            opacity: layer.opacity,
+            blend_mode: layer.blend_mode,
+            offset: (layer.left, layer.top),
+            data: img_to_pyarray(layer.image, layer.channels, mode, format, py),
+        })
+        .collect())
+}
+
+/// Writes a layered psd file with a flattened composite.
+///
+/// # Arguments
+///
+/// * `layers` - `PsdLayer`s bottom to top, "group" and "group_end" layers open
+///   and close a layer group (the group layer comes after its content).
+/// * `path` - Path of the psd file.
+/// * `bit_depth` - 8 or 16, by default 16 when a layer is an u16 array, else 8.
+/// * `size` - (width, height) of the canvas, by default the extent of the layers.
+///
+/// # Errors
+///
+/// This function will return an error if a layer array or blend mode is invalid
+/// or the file can not be written.
+#[pyfunction]
+#[pyo3(signature = (layers, path, bit_depth=None, size=None))]
+pub fn save_psd(
+    layers: Vec<PyRef<PyPsdLayer>>,
+    path: String,
+    bit_depth: Option<u8>,
+    size: Option<(u32, u32)>,
+    py: Python,
+) -> PyResult<()> {
+    let options = SaveOptions {
+        bit_depth,
+        ..Default::default()
+    };
+    let mut depth = bit_depth.unwrap_or(8) as u16;
+    let mut psd_layers = Vec::with_capacity(layers.len());
+    for layer in &layers {
+        let kind = PsdLayerKind::from_name(&layer.kind).ok_or_else(|| {
+            PyValueError::new_err(format!("Unsupported layer kind: {}", layer.kind))
+        })?;
+        let image = if !kind.has_pixels() {
+            DynamicImage::new_rgba8(0, 0)
+        } else {
+            let (vec_img, shape) = array_to_vec(&layer.data, &options, py)?;
+            if bit_depth.is_none() && matches!(vec_img, ImgVec::U16(_)) {
+                depth = 16;
+            }
+            vec_to_img(vec_img, &shape, None)
+                .map_err(|err| PyOSError::new_err(format!("Error saving image: {}", err)))?
+        };
+        psd_layers.push(PsdLayer {
+            name: layer.name.clone(),
+            kind,
+            visible: layer.visible,
+            opacity: layer.opacity,
+            blend_mode: layer.blend_mode.clone(),
+            top: layer.offset.1,
+            left: layer.offset.0,
+            channels: image.color().channel_count(),
+            image,
+        });
+    }
+    py.allow_threads(|| {
+        let bytes = write_psd(&psd_layers, size, depth).map_err(|err| err.to_string())?;
+        std::fs::write(&path, bytes).map_err(|err| err.to_string())
+    })
+    .map_err(|err| PyOSError::new_err(format!("Error saving image: {}", err)))
+}
@@ -62,18 +62,20 @@ pub fn resize_img<'py>(
     sampling: Option<u8>,
     py: Python,
 ) -> PyResult<PyObject> {
-    let array = input.as_array().to_owned();
-    let shape = array.shape().to_vec();
+    let input = input.as_array().to_owned();
+    let shape = input.shape().to_vec();
     let pixel_type = img_shape_to_pixel_type(&shape);
-    let mut resize = Image::new(size.0, size.1, pixel_type);
-    let img = array.into_raw_vec();
-    let img = ImageRef::new(shape[1] as u32, shape[0] as u32, img.as_bytes(), pixel_type).unwrap();
-    let mut resizer = Resizer::new();
-    resizer.resize(&img, &mut resize, &ResizeOptions::new().resize_alg(get_res_opt(&filter.unwrap_or(ResizeFilters::Nearest),conv.unwrap_or(false),sampling).unwrap())).unwrap();
-    let result_vec:&[f32] = cast_slice(resize.buffer());
+    let result_vec: Vec<f32> = py.allow_threads(|| {
+        let mut resize = Image::new(size.0, size.1, pixel_type);
+        let img = input.into_raw_vec();
+        let img = ImageRef::new(shape[1] as u32, shape[0] as u32, img.as_bytes(), pixel_type).unwrap();
+        let mut resizer = Resizer::new();
+        resizer.resize(&img, &mut resize, &ResizeOptions::new().resize_alg(get_res_opt(&filter.unwrap_or(ResizeFilters::Nearest),conv.unwrap_or(false),sampling).unwrap())).unwrap();
+        cast_slice(resize.buffer()).to_vec()
+    });
     if shape.get(2).is_some() {
-        Ok(PyArray::from_vec(py, result_vec.to_vec()).to_dyn().reshape([size.1 as Ix,size.0 as Ix,shape[2] as Ix])?.into_py(py))
+        Ok(PyArray::from_vec(py, result_vec).to_dyn().reshape([size.1 as Ix,size.0 as Ix,shape[2] as Ix])?.into_py(py))
     } else {
-        Ok(PyArray::from_vec(py, result_vec.to_vec()).to_dyn().reshape([size.1 as Ix,size.0 as Ix])?.into_py(py))
+        Ok(PyArray::from_vec(py, result_vec).to_dyn().reshape([size.1 as Ix,size.0 as Ix])?.into_py(py))
     }
 }
@@ -0,0 +1,339 @@
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Cursor, Read, Write};
+use std::path::Path;
+
+use image::ImageFormat;
+use ndarray::ArrayD;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuildError;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::utils::core::enums::SaveFormat;
+use crate::utils::image::save::{encode_img_vec, ImgVec, SaveOptions};
+
+// Reader and writer for comic archives (cbz/zip): pages are decoded straight from
+// the archive bytes, nothing is extracted to disk.
+
+/// Largest uncompressed page entry.
+const MAX_PAGE_SIZE: u64 = 512 << 20;
+
+/// ComicInfo.xml elements in the order of the ComicInfo 2.0 schema (without the
+/// nested Pages element).
+const COMIC_INFO_FIELDS: [&str; 43] = [
+    "Title",
+    "Series",
+    "Number",
+    "Count",
+    "Volume",
+    "AlternateSeries",
+    "AlternateNumber",
+    "AlternateCount",
+    "Summary",
+    "Notes",
+    "Year",
+    "Month",
+    "Day",
+    "Writer",
+    "Penciller",
+    "Inker",
+    "Colorist",
+    "Letterer",
+    "CoverArtist",
+    "Editor",
+    "Translator",
+    "Publisher",
+    "Imprint",
+    "Genre",
+    "Tags",
+    "Web",
+    "PageCount",
+    "LanguageISO",
+    "Format",
+    "BlackAndWhite",
+    "Manga",
+    "Characters",
+    "Teams",
+    "Locations",
+    "ScanInformation",
+    "StoryArc",
+    "StoryArcNumber",
+    "SeriesGroup",
+    "AgeRating",
+    "CommunityRating",
+    "MainCharacterOrTeam",
+    "Review",
+    "GTIN",
+];
+
+type Archive<'a> = ZipArchive<Cursor<&'a [u8]>>;
+
+fn open_archive(bytes: &[u8]) -> Result<Archive<'_>, Box<dyn Error>> {
+    Ok(ZipArchive::new(Cursor::new(bytes))?)
+}
+
+/// Whether an archive entry is a page: an image file that is not a directory,
+/// a hidden file or macOS resource fork.
+fn is_page(name: &str) -> bool {
+    let path = Path::new(name);
+    let hidden = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_none_or(|name| name.starts_with('.'));
+    let psd = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("psd"));
+    !name.ends_with('/')
+        && !hidden
+        && !name.starts_with("__MACOSX/")
+        && (psd || ImageFormat::from_path(path).is_ok())
+}
+
+/// Compares names with digit runs as numbers, so "page2" sorts before "page10".
+pub(crate) fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+    loop {
+        match (a.first(), b.first()) {
+            (None, None) => return Ordering::Equal,
+            (None, _) => return Ordering::Less,
+            (_, None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let digits = |s: &[u8]| s.iter().take_while(|c| c.is_ascii_digit()).count();
+                let (na, nb) = (digits(a), digits(b));
+                let trim = |s: &[u8]| s.iter().position(|&c| c != b'0').unwrap_or(s.len());
+                let (da, db) = (&a[trim(&a[..na])..na], &b[trim(&b[..nb])..nb]);
+                // longer number without leading zeros is bigger, then digit by digit
+                let ord = da.len().cmp(&db.len()).then_with(|| da.cmp(db));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                a = &a[na..];
+                b = &b[nb..];
+            }
+            (Some(x), Some(y)) => {
+                let ord = x.to_ascii_lowercase().cmp(&y.to_ascii_lowercase());
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                a = &a[1..];
+                b = &b[1..];
+            }
+        }
+    }
+}
+
+/// Lists the image entries of an archive in natural sort order.
+pub fn archive_pages(bytes: &[u8]) -> Result<Vec<String>, Box<dyn Error>> {
+    let archive = open_archive(bytes)?;
+    let mut pages: Vec<String> = archive
+        .file_names()
+        .filter(|name| is_page(name))
+        .map(String::from)
+        .collect();
+    pages.sort_by(|a, b| natural_cmp(a, b).then_with(|| a.cmp(b)));
+    Ok(pages)
+}
+
+fn entry_bytes(archive: &mut Archive, name: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let entry = archive.by_name(name)?;
+    let too_big = || {
+        format!(
+            "archive entry {} is over the {} byte page limit",
+            name, MAX_PAGE_SIZE
+        )
+    };
+    if entry.size() > MAX_PAGE_SIZE {
+        return Err(too_big().into());
+    }
+    // the declared size comes from the archive, the data can still be longer
+    let mut bytes = Vec::with_capacity(entry.size().min(64 << 20) as usize);
+    entry.take(MAX_PAGE_SIZE + 1).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > MAX_PAGE_SIZE {
+        return Err(too_big().into());
+    }
+    Ok(bytes)
+}
+
+/// Decodes the archive entry `name` with `read`.
+pub fn archive_read<T, F>(bytes: &[u8], name: &str, read: F) -> Result<ArrayD<T>, Box<dyn Error>>
+where
+    F: Fn(&[u8]) -> Result<ArrayD<T>, Box<dyn Error>>,
+{
+    let mut archive = open_archive(bytes)?;
+    read(&entry_bytes(&mut archive, name)?)
+}
+
+/// Decodes the archive entries `names` with `read` on a rayon pool of `threads`
+/// workers (0 = one per core), every worker keeps its own view of the archive.
+///
+/// # Returns
+/// One result per entry in input order, errors are kept as messages like
+/// `all_read_batch`.
+pub fn archive_read_batch<T, F>(
+    bytes: &[u8],
+    names: &[String],
+    threads: usize,
+    read: F,
+) -> Result<Vec<Result<ArrayD<T>, String>>, ThreadPoolBuildError>
+where
+    T: Send,
+    F: Fn(&[u8]) -> Result<ArrayD<T>, Box<dyn Error>> + Sync,
+{
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()?;
+    Ok(pool.install(|| {
+        names
+            .par_iter()
+            .map_init(
+                || open_archive(bytes).map_err(|err| err.to_string()),
+                |archive, name| {
+                    let archive = archive.as_mut().map_err(|err| err.clone())?;
+                    entry_bytes(archive, name)
+                        .and_then(|entry| read(&entry))
+                        .map_err(|err| err.to_string())
+                },
+            )
+            .collect()
+    }))
+}
+
+/// Builds ComicInfo.xml from `(field, value)` pairs, PageCount is filled in when
+/// not given.
+fn comic_info(fields: &[(String, String)], pages: usize) -> Result<String, Box<dyn Error>> {
+    if let Some((field, _)) = fields
+        .iter()
+        .find(|(field, _)| !COMIC_INFO_FIELDS.contains(&field.as_str()))
+    {
+        return Err(format!("unknown ComicInfo field: {}", field).into());
+    }
+    let page_count = pages.to_string();
+    let mut xml = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n",
+        "<ComicInfo xmlns:xsd=\"http://www.w3.org/2001/XMLSchema\" ",
+        "xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\">\n"
+    ));
+    for name in COMIC_INFO_FIELDS {
+        let value = match fields.iter().rev().find(|(field, _)| field == name) {
+            Some((_, value)) => value,
+            None if name == "PageCount" => &page_count,
+            None => continue,
+        };
+        let value = value
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;");
+        xml.push_str(&format!("  <{0}>{1}</{0}>\n", name, value));
+    }
+    xml.push_str("</ComicInfo>\n");
+    Ok(xml)
+}
+
+/// Encodes the pages in parallel with the `save` encoders and writes them to a cbz
+/// as 001.png, 002.png... (at least 3 digits), with an optional ComicInfo.xml.
+///
+/// The images are stored without compression, they are compressed already.
+pub fn save_cbz(
+    pages: Vec<(ImgVec, Vec<usize>)>,
+    out_path: &Path,
+    format: SaveFormat,
+    options: &SaveOptions,
+    info: Option<&[(String, String)]>,
+) -> Result<(), Box<dyn Error>> {
+    let count = pages.len();
+    let info = info.map(|fields| comic_info(fields, count)).transpose()?;
+    let encoded: Vec<Vec<u8>> = pages
+        .into_par_iter()
+        .map(|(vec_img, shape)| {
+            encode_img_vec(vec_img, &shape, format, options).map_err(|err| err.to_string())
+        })
+        .collect::<Result<_, String>>()?;
+
+    let ext = ImageFormat::from(format).extensions_str()[0];
+    let digits = count.to_string().len().max(3);
+    let mut zip = ZipWriter::new(BufWriter::new(File::create(out_path)?));
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    for (i, page) in encoded.iter().enumerate() {
+        zip.start_file(format!("{:0digits$}.{}", i + 1, ext), stored)?;
+        zip.write_all(page)?;
+    }
+    if let Some(info) = info {
+        zip.start_file("ComicInfo.xml", SimpleFileOptions::default())?;
+        zip.write_all(info.as_bytes())?;
+    }
+    zip.finish()?.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn natural_order() {
+        let mut names = vec![
+            "ch1/page10.png",
+            "ch1/page2.png",
+            "ch1/Page1.png",
+            "ch10/page1.png",
+            "ch2/page001.png",
+            "ch1/page02b.png",
+        ];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            names,
+            [
+                "ch1/Page1.png",
+                "ch1/page2.png",
+                "ch1/page02b.png",
+                "ch1/page10.png",
+                "ch2/page001.png",
+                "ch10/page1.png",
+            ]
+        );
+    }
+
+    #[test]
+    fn comic_info_order() {
+        let fields = [
+            ("Writer".to_string(), "A & B".to_string()),
+            ("Title".to_string(), "<Ch. 1>".to_string()),
+        ];
+        let xml = comic_info(&fields, 12).unwrap();
+        let title = xml.find("<Title>&lt;Ch. 1&gt;</Title>").unwrap();
+        let writer = xml.find("<Writer>A &amp; B</Writer>").unwrap();
+        let pages = xml.find("<PageCount>12</PageCount>").unwrap();
+        assert!(title < writer && writer < pages);
+        assert!(comic_info(&[("Tittle".to_string(), String::new())], 1).is_err());
+    }
+
+    #[test]
+    fn oversized_entry_fails() {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        zip.start_file("001.png", stored).unwrap();
+        zip.write_all(b"page").unwrap();
+        let mut bytes = zip.finish().unwrap().into_inner();
+        // claim 4 GB in the local and central headers
+        let local = bytes.windows(4).position(|w| w == b"PK\x03\x04").unwrap();
+        bytes[local + 22..local + 26].copy_from_slice(&u32::MAX.to_le_bytes());
+        let central = bytes.windows(4).position(|w| w == b"PK\x01\x02").unwrap();
+        bytes[central + 24..central + 28].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut archive = open_archive(&bytes).unwrap();
+        let err = entry_bytes(&mut archive, "001.png").unwrap_err();
+        assert!(err.to_string().contains("page limit"));
+    }
+
+    #[test]
+    fn page_filter() {
+        assert!(is_page("001.jpg"));
+        assert!(is_page("scans/002.PSD"));
+        assert!(!is_page("ComicInfo.xml"));
+        assert!(!is_page("__MACOSX/._001.jpg"));
+        assert!(!is_page("scans/.thumb.png"));
+        assert!(!is_page("scans/"));
+    }
+}
@@ -0,0 +1,693 @@
+use std::error::Error;
+use std::f32::consts::PI;
+
+// Baseline (sequential huffman) jpeg decoder that hands out the image one row of
+// mcus at a time, at full size or straight at 1/2, 1/4 or 1/8 of it by running a
+// reduced idct on the low frequency coefficients of every block. Previews and
+// strips of tall images use it, it holds at most two mcu rows of samples.
+
+/// Zigzag position to natural (row major) position of the 8x8 coefficients.
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// Bits looked up at once when decoding huffman codes, longer codes are searched.
+const LOOKUP_BITS: u32 = 9;
+
+struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    quant: usize,
+    dc: usize,
+    ac: usize,
+}
+
+struct Huffman {
+    /// (code length, symbol) for every `LOOKUP_BITS` prefix, length 0 = longer code
+    lookup: Vec<(u8, u8)>,
+    max_code: [i32; 17],
+    offset: [i32; 17],
+    symbols: Vec<u8>,
+}
+
+impl Huffman {
+    fn new(counts: &[u8], symbols: &[u8]) -> Result<Huffman, Box<dyn Error>> {
+        let mut lookup = vec![(0, 0); 1 << LOOKUP_BITS];
+        let mut max_code = [-1; 17];
+        let mut offset = [0; 17];
+        let (mut code, mut k) = (0i32, 0usize);
+        for len in 1..=16 {
+            let count = counts[len - 1] as usize;
+            // symbols of this length sit at k.., their codes start at `code`
+            offset[len] = k as i32 - code;
+            for _ in 0..count {
+                // more codes than fit in `len` bits
+                if code >= 1 << len {
+                    return Err("jpeg huffman table is overfull".into());
+                }
+                if len <= LOOKUP_BITS as usize && k < symbols.len() {
+                    let shift = LOOKUP_BITS as usize - len;
+                    let start = (code as usize) << shift;
+                    lookup[start..start + (1 << shift)].fill((len as u8, symbols[k]));
+                }
+                code += 1;
+                k += 1;
+            }
+            if count > 0 {
+                max_code[len] = code - 1;
+            }
+            code <<= 1;
+        }
+        Ok(Huffman {
+            lookup,
+            max_code,
+            offset,
+            symbols: symbols.to_vec(),
+        })
+    }
+
+    fn decode<B: AsRef<[u8]>>(&self, bits: &mut BitReader<B>) -> Result<u8, Box<dyn Error>> {
+        let peek = bits.peek(16);
+        let (len, symbol) = self.lookup[(peek >> (16 - LOOKUP_BITS)) as usize];
+        if len > 0 {
+            bits.skip(len as u32);
+            return Ok(symbol);
+        }
+        for len in LOOKUP_BITS as usize + 1..=16 {
+            let code = (peek >> (16 - len)) as i32;
+            if code <= self.max_code[len] {
+                bits.skip(len as u32);
+                return self
+                    .symbols
+                    .get((code + self.offset[len]) as usize)
+                    .copied()
+                    .ok_or_else(|| "jpeg huffman code is out of range".into());
+            }
+        }
+        Err("jpeg has a bad huffman code".into())
+    }
+}
+
+/// Reads the entropy coded segment, stuffed zero bytes are dropped and zeros are
+/// fed once a marker is reached.
+struct BitReader<B> {
+    data: B,
+    pos: usize,
+    bits: u64,
+    count: u32,
+    marker: bool,
+}
+
+impl<B: AsRef<[u8]>> BitReader<B> {
+    fn fill(&mut self) {
+        let data = self.data.as_ref();
+        while self.count <= 56 {
+            let mut byte = 0;
+            if !self.marker && self.pos < data.len() {
+                byte = data[self.pos];
+                if byte == 0xFF {
+                    if data.get(self.pos + 1) == Some(&0) {
+                        self.pos += 2;
+                    } else {
+                        self.marker = true;
+                        byte = 0;
+                    }
+                } else {
+                    self.pos += 1;
+                }
+            }
+            self.bits |= (byte as u64) << (56 - self.count);
+            self.count += 8;
+        }
+    }
+
+    fn peek(&mut self, n: u32) -> u32 {
+        if self.count < n {
+            self.fill();
+        }
+        (self.bits >> (64 - n)) as u32
+    }
+
+    fn skip(&mut self, n: u32) {
+        self.bits <<= n;
+        self.count -= n;
+    }
+
+    /// Reads `n` bits as a signed coefficient (F.2.2.1 of the spec).
+    fn receive_extend(&mut self, n: u32) -> i32 {
+        if n == 0 {
+            return 0;
+        }
+        let value = self.peek(n) as i32;
+        self.skip(n);
+        if value < 1 << (n - 1) {
+            value - (1 << n) + 1
+        } else {
+            value
+        }
+    }
+
+    /// Drops the buffered bits and moves past the next restart marker.
+    fn restart(&mut self) {
+        self.bits = 0;
+        self.count = 0;
+        self.marker = false;
+        let data = self.data.as_ref();
+        while self.pos + 1 < data.len()
+            && !(data[self.pos] == 0xFF && (0xD0..=0xD7).contains(&data[self.pos + 1]))
+        {
+            self.pos += 1;
+        }
+        self.pos += 2;
+    }
+}
+
+pub(crate) struct BaselineJpeg<B> {
+    /// Output size, 1/scale of the image rounded up.
+    pub width: usize,
+    pub height: usize,
+    /// 1 for gray and 3 for rgb output.
+    pub channels: usize,
+    /// Block size of the output, 8 / scale.
+    n: usize,
+    components: Vec<Component>,
+    quant: [[u16; 64]; 4],
+    dc_tables: [Option<Huffman>; 4],
+    ac_tables: [Option<Huffman>; 4],
+    restart_interval: usize,
+    rgb: bool,
+    h_max: usize,
+    v_max: usize,
+    mcus_x: usize,
+    mcus_y: usize,
+    idct: [f32; 64],
+    bits: BitReader<B>,
+    predictors: Vec<i32>,
+    /// Mcu rows decoded so far.
+    mcu_rows: usize,
+    /// Planes of the next mcu row, upsampled chroma needs the row below.
+    ahead: Option<Vec<Vec<u8>>>,
+    /// Last row of every plane of the mcu row above.
+    above: Vec<Vec<u8>>,
+    /// Output rows handed out so far.
+    rows: usize,
+}
+
+impl<B: AsRef<[u8]>> BaselineJpeg<B> {
+    /// Reads the headers up to the scan of a jpeg to decode at 1/`scale` (1, 2, 4 or 8).
+    ///
+    /// # Returns
+    /// None for anything the decoder does not handle (other scales, progressive,
+    /// arithmetic, 12 bit or cmyk jpegs and non jpegs).
+    pub fn new(bytes: B, scale: u32) -> Result<Option<BaselineJpeg<B>>, Box<dyn Error>> {
+        let file = bytes.as_ref();
+        if !matches!(scale, 1 | 2 | 4 | 8) || !file.starts_with(&[0xFF, 0xD8]) {
+            return Ok(None);
+        }
+        let mut quant = [[0u16; 64]; 4];
+        let mut dc_tables: [Option<Huffman>; 4] = Default::default();
+        let mut ac_tables: [Option<Huffman>; 4] = Default::default();
+        let mut components = Vec::new();
+        let (mut width, mut height) = (0, 0);
+        let mut restart_interval = 0;
+        let mut adobe_transform = None;
+        let mut pos = 2;
+        loop {
+            // markers may be preceded by any number of fill bytes
+            while file.get(pos) == Some(&0xFF) && file.get(pos + 1) == Some(&0xFF) {
+                pos += 1;
+            }
+            // standalone markers carry no length
+            if let Some([0xFF, 0x01 | 0xD0..=0xD7]) = file.get(pos..pos + 2) {
+                pos += 2;
+                continue;
+            }
+            let (marker, len) = match file.get(pos..pos + 4) {
+                Some([0xFF, marker, hi, lo]) => (*marker, u16::from_be_bytes([*hi, *lo]) as usize),
+                _ => return Err("jpeg is truncated".into()),
+            };
+            let data = file
+                .get(pos + 4..pos + 2 + len.max(2))
+                .ok_or("jpeg is truncated")?;
+            pos += 2 + len;
+            match marker {
+                0xDB => {
+                    let mut data = data;
+                    while let Some(&pq_tq) = data.first() {
+                        let (wide, id) = (pq_tq >> 4 == 1, (pq_tq & 3) as usize);
+                        let size = if wide { 128 } else { 64 };
+                        let table = data
+                            .get(1..1 + size)
+                            .ok_or("jpeg quant table is truncated")?;
+                        for (k, q) in quant[id].iter_mut().enumerate() {
+                            *q = if wide {
+                                u16::from_be_bytes([table[k * 2], table[k * 2 + 1]])
+                            } else {
+                                table[k] as u16
+                            };
+                        }
+                        data = &data[1 + size..];
+                    }
+                }
+                0xC4 => {
+                    let mut data = data;
+                    while let Some(&tc_th) = data.first() {
+                        let counts = data.get(1..17).ok_or("jpeg huffman table is truncated")?;
+                        let total: usize = counts.iter().map(|&c| c as usize).sum();
+                        let symbols = data
+                            .get(17..17 + total)
+                            .ok_or("jpeg huffman table is truncated")?;
+                        // coefficient sizes past 11 bits (dc) and 10 bits (ac) do not exist
+                        let ac = tc_th >> 4 != 0;
+                        if symbols
+                            .iter()
+                            .any(|&symbol| if ac { symbol & 15 > 10 } else { symbol > 11 })
+                        {
+                            return Err("jpeg huffman table has a bad coefficient size".into());
+                        }
+                        let table = Some(Huffman::new(counts, symbols)?);
+                        match tc_th >> 4 {
+                            0 => dc_tables[(tc_th & 3) as usize] = table,
+                            _ => ac_tables[(tc_th & 3) as usize] = table,
+                        }
+                        data = &data[17 + total..];
+                    }
+                }
+                0xC0 | 0xC1 => {
+                    if data.len() < 6 || data[0] != 8 {
+                        return Ok(None);
+                    }
+                    height = u16::from_be_bytes([data[1], data[2]]) as usize;
+                    width = u16::from_be_bytes([data[3], data[4]]) as usize;
+                    for c in data[6..].chunks_exact(3).take(data[5] as usize) {
+                        components.push(Component {
+                            id: c[0],
+                            h: (c[1] >> 4).max(1) as usize,
+                            v: (c[1] & 15).max(1) as usize,
+                            quant: (c[2] & 3) as usize,
+                            dc: 0,
+                            ac: 0,
+                        });
+                    }
+                }
+                // progressive, lossless and arithmetic coded frames
+                0xC2 | 0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => return Ok(None),
+                0xDD if data.len() >= 2 => {
+                    restart_interval = u16::from_be_bytes([data[0], data[1]]) as usize;
+                }
+                0xEE if data.starts_with(b"Adobe") && data.len() >= 12 => {
+                    adobe_transform = Some(data[11]);
+                }
+                0xDA => {
+                    let count = *data.first().ok_or("jpeg scan header is truncated")? as usize;
+                    // a baseline file split into one scan per component is left to the full decoder
+                    if count != components.len() || width == 0 || height == 0 {
+                        return Ok(None);
+                    }
+                    for selector in data[1..].chunks_exact(2).take(count) {
+                        let component = components
+                            .iter_mut()
+                            .find(|c| c.id == selector[0])
+                            .ok_or("jpeg scan refers to an unknown component")?;
+                        component.dc = (selector[1] >> 4 & 3) as usize;
+                        component.ac = (selector[1] & 3) as usize;
+                    }
+                    break;
+                }
+                0xD9 => return Err("jpeg has no image data".into()),
+                _ => {}
+            }
+        }
+        let rgb = match (components.len(), adobe_transform) {
+            (1, _) => false,
+            (3, Some(0)) => true,
+            (3, None) => components.iter().map(|c| c.id).eq(*b"RGB"),
+            (3, _) => false,
+            _ => return Ok(None),
+        };
+
+        let n = 8 / scale as usize;
+        if components.len() == 1 {
+            // a single component scan is not interleaved, one block per mcu
+            components[0].h = 1;
+            components[0].v = 1;
+        }
+        let h_max = components.iter().map(|c| c.h).max().unwrap_or(1);
+        let v_max = components.iter().map(|c| c.v).max().unwrap_or(1);
+        Ok(Some(BaselineJpeg {
+            width: width.div_ceil(scale as usize),
+            height: height.div_ceil(scale as usize),
+            channels: if components.len() == 1 { 1 } else { 3 },
+            n,
+            predictors: vec![0; components.len()],
+            components,
+            quant,
+            dc_tables,
+            ac_tables,
+            restart_interval,
+            rgb,
+            h_max,
+            v_max,
+            mcus_x: width.div_ceil(8 * h_max),
+            mcus_y: height.div_ceil(8 * v_max),
+            idct: idct_table(n),
+            bits: BitReader {
+                data: bytes,
+                pos,
+                bits: 0,
+                count: 0,
+                marker: false,
+            },
+            mcu_rows: 0,
+            ahead: None,
+            above: Vec::new(),
+            rows: 0,
+        }))
+    }
+
+    /// Decodes the next row of mcus.
+    ///
+    /// # Returns
+    /// The next `8 / scale` rows per vertical sampling factor (fewer at the bottom)
+    /// with `channels` interleaved samples, None after the last row.
+    pub fn next_band(&mut self) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        if self.rows >= self.height {
+            return Ok(None);
+        }
+        let planes = match self.ahead.take() {
+            Some(planes) => planes,
+            None => self.decode_mcu_row()?,
+        };
+        if self.mcu_rows < self.mcus_y {
+            self.ahead = Some(self.decode_mcu_row()?);
+        }
+        let widths: Vec<usize> = (self.components.iter())
+            .map(|c| self.mcus_x * c.h * self.n)
+            .collect();
+        let first_rows = |planes: &[Vec<u8>]| -> Vec<Vec<u8>> {
+            planes
+                .iter()
+                .zip(&widths)
+                .map(|(p, &w)| p[..w].to_vec())
+                .collect()
+        };
+        let last_rows = |planes: &[Vec<u8>]| -> Vec<Vec<u8>> {
+            (planes.iter().zip(&widths))
+                .map(|(p, &w)| p[p.len() - w..].to_vec())
+                .collect()
+        };
+        // the image edges repeat their outer rows
+        if self.above.is_empty() {
+            self.above = first_rows(&planes);
+        }
+        let below = match &self.ahead {
+            Some(ahead) => first_rows(ahead),
+            None => last_rows(&planes),
+        };
+
+        let band_rows = (self.v_max * self.n).min(self.height - self.rows);
+        let (width, channels) = (self.width, self.channels);
+        let mut px = vec![0u8; band_rows * width * channels];
+        let mut scratch = Vec::new();
+        let mut rows = vec![vec![0u8; width]; channels];
+        for (y, out) in px.chunks_exact_mut(width * channels).enumerate() {
+            for (c, component) in self.components.iter().enumerate() {
+                upsample_row(
+                    (&planes[c], &self.above[c], &below[c]),
+                    (component.h, component.v),
+                    (self.h_max, self.v_max),
+                    y,
+                    &mut scratch,
+                    &mut rows[c],
+                );
+            }
+            if channels == 1 {
+                out.copy_from_slice(&rows[0]);
+                continue;
+            }
+            for (x, out) in out.chunks_exact_mut(3).enumerate() {
+                let (a, b, c) = (rows[0][x], rows[1][x], rows[2][x]);
+                out.copy_from_slice(&if self.rgb {
+                    [a, b, c]
+                } else {
+                    ycc_to_rgb8(a, b, c)
+                });
+            }
+        }
+        self.above = last_rows(&planes);
+        self.rows += band_rows;
+        Ok(Some(px))
+    }
+
+    /// Decodes the blocks of the next mcu row into one plane per component.
+    fn decode_mcu_row(&mut self) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+        let n = self.n;
+        let mut planes: Vec<Vec<u8>> = (self.components.iter())
+            .map(|c| vec![0; self.mcus_x * c.h * n * c.v * n])
+            .collect();
+        let mut coeffs = [0i32; 64];
+        let mut block = [0u8; 64];
+        for mx in 0..self.mcus_x {
+            let mcu = self.mcu_rows * self.mcus_x + mx;
+            if self.restart_interval > 0 && mcu > 0 && mcu.is_multiple_of(self.restart_interval) {
+                self.bits.restart();
+                self.predictors.fill(0);
+            }
+            for (c, component) in self.components.iter().enumerate() {
+                let dc = self.dc_tables[component.dc]
+                    .as_ref()
+                    .ok_or("jpeg huffman table is missing")?;
+                let ac = self.ac_tables[component.ac]
+                    .as_ref()
+                    .ok_or("jpeg huffman table is missing")?;
+                let q = &self.quant[component.quant];
+                let plane_width = self.mcus_x * component.h * n;
+                for by in 0..component.v {
+                    for bx in 0..component.h {
+                        coeffs.fill(0);
+                        let size = dc.decode(&mut self.bits)? as u32;
+                        self.predictors[c] =
+                            self.predictors[c].wrapping_add(self.bits.receive_extend(size));
+                        coeffs[0] = dequantize(self.predictors[c], q[0]);
+                        let mut flat = true;
+                        let mut last_row = 0;
+                        let mut k = 1;
+                        while k < 64 {
+                            let rs = ac.decode(&mut self.bits)?;
+                            let (run, size) = ((rs >> 4) as usize, (rs & 15) as u32);
+                            if size == 0 {
+                                if run != 15 {
+                                    break;
+                                }
+                                k += 16;
+                                continue;
+                            }
+                            k += run;
+                            if k > 63 {
+                                return Err("jpeg block has too many coefficients".into());
+                            }
+                            let value = self.bits.receive_extend(size);
+                            let (v, u) = (ZIGZAG[k] / 8, ZIGZAG[k] % 8);
+                            if u < n && v < n {
+                                coeffs[v * n + u] = dequantize(value, q[k]);
+                                flat = false;
+                                last_row = last_row.max(v);
+                            }
+                            k += 1;
+                        }
+                        // blocks without ac terms at this size are flat
+                        let rows = last_row + 1;
+                        match n {
+                            _ if flat => block.fill(level_shift(coeffs[0] as f32 / 8.0)),
+                            2 => idct::<2>(&coeffs, &self.idct, rows, &mut block),
+                            4 => idct::<4>(&coeffs, &self.idct, rows, &mut block),
+                            _ => idct8(&coeffs, &mut block),
+                        }
+                        let x0 = (mx * component.h + bx) * n;
+                        for y in 0..n {
+                            let row = (by * n + y) * plane_width + x0;
+                            planes[c][row..row + n].copy_from_slice(&block[y * n..y * n + n]);
+                        }
+                    }
+                }
+            }
+        }
+        self.mcu_rows += 1;
+        Ok(planes)
+    }
+}
+
+/// Upsamples row `y` of the band of a component to the output width. Samples at half
+/// the resolution are interpolated with the triangle filter of libjpeg (vertically,
+/// then horizontally), other factors repeat their samples.
+fn upsample_row(
+    (plane, above, below): (&[u8], &[u8], &[u8]),
+    (h, v): (usize, usize),
+    (h_max, v_max): (usize, usize),
+    y: usize,
+    scratch: &mut Vec<u16>,
+    out: &mut [u8],
+) {
+    let plane_width = above.len();
+    let plane_rows = plane.len() / plane_width;
+    let row = |r: Option<usize>| match r {
+        None => above,
+        Some(r) if r >= plane_rows => below,
+        Some(r) => &plane[r * plane_width..(r + 1) * plane_width],
+    };
+    scratch.clear();
+    if v_max == 2 * v {
+        let near = y / 2;
+        let far = if y.is_multiple_of(2) {
+            near.checked_sub(1)
+        } else {
+            Some(near + 1)
+        };
+        let rows = row(Some(near)).iter().zip(row(far));
+        scratch.extend(rows.map(|(&near, &far)| (3 * near as u16 + far as u16 + 2) >> 2));
+    } else {
+        scratch.extend(row(Some(y * v / v_max)).iter().map(|&s| s as u16));
+    }
+    let last = scratch.len() - 1;
+    if h_max == 2 * h {
+        for (x, out) in out.iter_mut().enumerate() {
+            let near = (x / 2).min(last);
+            let far = if x.is_multiple_of(2) {
+                near.saturating_sub(1)
+            } else {
+                (near + 1).min(last)
+            };
+            *out = ((3 * scratch[near] + scratch[far] + 2) >> 2) as u8;
+        }
+    } else {
+        for (x, out) in out.iter_mut().enumerate() {
+            *out = scratch[(x * h / h_max).min(last)] as u8;
+        }
+    }
+}
+
+/// Jfif full range ycbcr to rgb in 16 bit fixed point.
+fn ycc_to_rgb8(y: u8, cb: u8, cr: u8) -> [u8; 3] {
+    let (y, cb, cr) = ((y as i32) << 16, cb as i32 - 128, cr as i32 - 128);
+    let round = 1 << 15;
+    [
+        y + 91881 * cr + round,
+        y - 22554 * cb - 46802 * cr + round,
+        y + 116130 * cb + round,
+    ]
+    .map(|v| (v >> 16).clamp(0, 255) as u8)
+}
+
+/// Level shifts an idct output to 0-255, rounded to nearest.
+fn level_shift(value: f32) -> u8 {
+    (value + 128.5).clamp(0.0, 255.0) as u8
+}
+
+/// Basis of the `n` point idct, scaled so the dc of every size is the block mean.
+fn idct_table(n: usize) -> [f32; 64] {
+    let mut table = [0.0; 64];
+    for x in 0..n {
+        for u in 0..n {
+            let c = if u == 0 { 0.5f32.sqrt() } else { 1.0 };
+            let angle = (2 * x + 1) as f32 * u as f32 * PI / (2 * n) as f32;
+            table[x * n + u] = c * angle.cos() / 2.0;
+        }
+    }
+    table
+}
+
+/// Runs the `N` x `N` idct on the top left coefficients, level shifted to 0-255.
+/// Coefficient rows from `rows` on are zero.
+fn idct<const N: usize>(coeffs: &[i32; 64], table: &[f32; 64], rows: usize, out: &mut [u8; 64]) {
+    let mut tmp = [0f32; 64];
+    for v in 0..rows {
+        for x in 0..N {
+            let mut sum = 0.0;
+            for u in 0..N {
+                sum += table[x * N + u] * coeffs[v * N + u] as f32;
+            }
+            tmp[v * N + x] = sum;
+        }
+    }
+    for y in 0..N {
+        for x in 0..N {
+            let mut sum = 0.0;
+            for v in 0..rows {
+                sum += table[y * N + v] * tmp[v * N + x];
+            }
+            out[y * N + x] = level_shift(sum);
+        }
+    }
+}
+
+/// Dequantized coefficient, clamped to 16 bits like libjpeg so the idct can not
+/// overflow on broken files.
+fn dequantize(value: i32, q: u16) -> i32 {
+    (value as i64 * q as i64).clamp(i16::MIN as i64, i16::MAX as i64) as i32
+}
+
+/// 12 bit fixed point constant of the integer idct.
+const fn fixed(x: f64) -> i64 {
+    (x * 4096.0 + 0.5) as i64
+}
+
+/// Full size integer idct of libjpeg (islow), level shifted to 0-255.
+fn idct8(coeffs: &[i32; 64], out: &mut [u8; 64]) {
+    let mut tmp = [0i64; 64];
+    for x in 0..8 {
+        let column: [i64; 8] = std::array::from_fn(|v| coeffs[v * 8 + x] as i64);
+        // columns without ac terms stay flat
+        let column = if column[1..].iter().all(|&c| c == 0) {
+            [column[0] * 4; 8]
+        } else {
+            idct_1d(column, 512, 10)
+        };
+        for (v, value) in column.into_iter().enumerate() {
+            tmp[v * 8 + x] = value;
+        }
+    }
+    for y in 0..8 {
+        let row: [i64; 8] = std::array::from_fn(|x| tmp[y * 8 + x]);
+        let row = idct_1d(row, 65536 + (128 << 17), 17);
+        for (x, value) in row.into_iter().enumerate() {
+            out[y * 8 + x] = value.clamp(0, 255) as u8;
+        }
+    }
+}
+
+/// One pass of `idct8`, `bias` is added before the results are shifted down by
+/// `shift` bits.
+fn idct_1d(s: [i64; 8], bias: i64, shift: u32) -> [i64; 8] {
+    let p1 = (s[2] + s[6]) * fixed(0.5411961);
+    let t2 = p1 + s[6] * fixed(-1.847759065);
+    let t3 = p1 + s[2] * fixed(0.765366865);
+    let t0 = (s[0] + s[4]) << 12;
+    let t1 = (s[0] - s[4]) << 12;
+    let (x0, x3) = (t0 + t3 + bias, t0 - t3 + bias);
+    let (x1, x2) = (t1 + t2 + bias, t1 - t2 + bias);
+
+    let (t0, t1, t2, t3) = (s[7], s[5], s[3], s[1]);
+    let (p3, p4) = (t0 + t2, t1 + t3);
+    let p5 = (p3 + p4) * fixed(1.175875602);
+    let p1 = p5 + (t0 + t3) * fixed(-0.899976223);
+    let p2 = p5 + (t1 + t2) * fixed(-2.562915447);
+    let (p3, p4) = (p3 * fixed(-1.96157056), p4 * fixed(-0.390180644));
+    let t0 = t0 * fixed(0.298631336) + p1 + p3;
+    let t1 = t1 * fixed(2.053119869) + p2 + p4;
+    let t2 = t2 * fixed(3.072711026) + p2 + p3;
+    let t3 = t3 * fixed(1.50132111) + p1 + p4;
+    [
+        x0 + t3,
+        x1 + t2,
+        x2 + t1,
+        x3 + t0,
+        x3 - t0,
+        x2 - t1,
+        x1 - t2,
+        x0 - t3,
+    ]
+    .map(|v| v >> shift)
+}
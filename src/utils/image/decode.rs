@@ -3,250 +3,362 @@ use std::io::Cursor;
 use std::path::Path;
 
 use filebuffer::FileBuffer;
-use ndarray::{Array2, Array3, ArrayD};
-use zune_psd::PSDDecoder;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder};
+use ndarray::ArrayD;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuildError;
 
-use crate::utils::core::convert::{
-    luma2array, luma2arrayf32, rgb2array, rgb2arrayf32, rgb8_to_gray32, rgb8_to_gray8, u16_to_f32,
-    u16_to_u8, u8_to_f32,
-};
+use crate::utils::core::convert::img2array;
 use crate::utils::core::enums::ImgColor;
+use crate::utils::image::icc::{to_srgb as icc_to_srgb, PSD_ICC_PROFILE};
+use crate::utils::image::native::cmyk_jpeg;
+use crate::utils::image::psd::{PsdFile, PSD_MAGIC};
+use crate::utils::image::scaled::{decode_scaled_jpeg, downscale};
 
-pub(crate) fn gray_img_open(bytes: &[u8]) -> Result<Array2<u8>, Box<dyn Error>> {
-    let img = image::io::Reader::new(Cursor::new(bytes))
-        .with_guessed_format()?
-        .decode()?;
-    let img_luma = img.to_luma8();
-    Ok(luma2array(img_luma))
+/// Decodes psd through the own reader, cmyk jpegs through zune-jpeg and everything
+/// else through the image crate. Cmyk, lab and psd multichannel images come out as rgb.
+///
+/// With `apply_orientation` the image is rotated/flipped upright by its exif orientation,
+/// with `to_srgb` the pixels are converted from the embedded icc profile to sRGB and
+/// with a `scale` of 2, 4 or 8 the image is read at 1/scale of its size (baseline jpegs
+/// are decoded at that size, everything else is box resized after decoding).
+///
+/// # Returns
+/// The decoded image in its source bit depth and the native channel count of the file.
+pub(crate) fn img_open(
+    bytes: &[u8],
+    apply_orientation: bool,
+    to_srgb: bool,
+    scale: u32,
+) -> Result<(DynamicImage, u8), Box<dyn Error>> {
+    let mut scaled = scale <= 1;
+    let (mut img, channels, orientation, icc) = if bytes.starts_with(&PSD_MAGIC) {
+        let psd = PsdFile::parse(bytes)?;
+        let orientation = psd.orientation().and_then(Orientation::from_exif);
+        let icc = psd.resource(PSD_ICC_PROFILE).map(<[u8]>::to_vec);
+        let (img, channels) = psd.decode()?;
+        (img, channels, orientation, icc)
+    } else {
+        let mut decoder = image::ImageReader::new(Cursor::new(bytes))
+            .with_guessed_format()?
+            .into_decoder()?;
+        let orientation = decoder.orientation().ok();
+        let icc = decoder.icc_profile().ok().flatten();
+        let img = if let Some(native) = cmyk_jpeg(bytes)? {
+            scaled = true;
+            native.downscale(scale).to_dynamic(icc.as_deref())?
+        } else if let Some(img) = decode_scaled_jpeg(bytes, scale)? {
+            scaled = true;
+            img
+        } else {
+            DynamicImage::from_decoder(decoder)?
+        };
+        let channels = img.color().channel_count();
+        (img, channels, orientation, icc)
+    };
+    if !scaled {
+        img = downscale(img, scale)?;
+    }
+    if let (true, Some(icc)) = (to_srgb, icc) {
+        img = icc_to_srgb(img, &icc)?;
+    }
+    if let (true, Some(orientation)) = (apply_orientation, orientation) {
+        img.apply_orientation(orientation);
+    }
+    Ok((img, channels))
 }
 
-pub(crate) fn rgb_img_open(bytes: &[u8]) -> Result<Array3<u8>, Box<dyn Error>> {
-    let img = image::io::Reader::new(Cursor::new(bytes))
-        .with_guessed_format()?
-        .decode()?;
-    let img_rgb8 = img.to_rgb8();
-    Ok(rgb2array(img_rgb8))
+/// Decodes the samples of cmyk, lab and multichannel psd files and of cmyk jpegs as
+/// stored (cmyk as ink amounts, 0 = no ink), scaled to `T` by `sample`.
+///
+/// # Returns
+/// None for every other image, those are read through `img_open`.
+fn native_open<T: Clone>(
+    bytes: &[u8],
+    apply_orientation: bool,
+    scale: u32,
+    sample: impl Fn(f32) -> T,
+) -> Result<Option<ArrayD<T>>, Box<dyn Error>> {
+    let (native, orientation) = if bytes.starts_with(&PSD_MAGIC) {
+        let psd = PsdFile::parse(bytes)?;
+        let orientation = psd.orientation().and_then(Orientation::from_exif);
+        (psd.decode_native()?, orientation)
+    } else {
+        let Some(native) = cmyk_jpeg(bytes)? else {
+            return Ok(None);
+        };
+        let mut decoder = image::ImageReader::new(Cursor::new(bytes))
+            .with_guessed_format()?
+            .into_decoder()?;
+        (Some(native), decoder.orientation().ok())
+    };
+    let orientation = orientation.filter(|_| apply_orientation);
+    native
+        .map(|native| native.downscale(scale).to_array(orientation, sample))
+        .transpose()
 }
 
-pub(crate) fn rgb_img_openf32(bytes: &[u8]) -> Result<Array3<f32>, Box<dyn Error>> {
-    let img = image::io::Reader::new(Cursor::new(bytes))
-        .with_guessed_format()?
-        .decode()?;
-    let img_rgb8 = img.to_rgb8();
-    Ok(rgb2arrayf32(img_rgb8))
+fn mode_channels(mode: ImgColor, native: u8) -> u8 {
+    match mode {
+        ImgColor::GRAY => 1,
+        ImgColor::GRAYA => 2,
+        ImgColor::RGB => 3,
+        ImgColor::RGBA => 4,
+        ImgColor::DYNAMIC => native,
+    }
 }
 
-pub(crate) fn gray_img_openf32(bytes: &[u8]) -> Result<Array2<f32>, Box<dyn Error>> {
-    let img = image::io::Reader::new(Cursor::new(bytes))
-        .with_guessed_format()?
-        .decode()?;
-    let img_luma = img.to_luma8();
-    Ok(luma2arrayf32(img_luma))
+pub(crate) fn img_to_u8(img: DynamicImage, native: u8, mode: ImgColor) -> ArrayD<u8> {
+    match mode_channels(mode, native) {
+        1 => img2array(img.to_luma8()),
+        2 => img2array(img.to_luma_alpha8()),
+        4 => img2array(img.to_rgba8()),
+        _ => img2array(img.to_rgb8()),
+    }
 }
 
+pub(crate) fn img_to_u16(img: DynamicImage, native: u8, mode: ImgColor) -> ArrayD<u16> {
+    match mode_channels(mode, native) {
+        1 => img2array(img.to_luma16()),
+        2 => img2array(img.to_luma_alpha16()),
+        4 => img2array(img.to_rgba16()),
+        _ => img2array(img.to_rgb16()),
+    }
+}
 
+pub(crate) fn img_to_f32(img: DynamicImage, native: u8, mode: ImgColor) -> ArrayD<f32> {
+    match mode_channels(mode, native) {
+        1 => img2array(img.to_luma32f()),
+        2 => img2array(img.to_luma_alpha32f()),
+        4 => img2array(img.to_rgba32f()),
+        _ => img2array(img.to_rgb32f()),
+    }
+}
 
-fn decode_size_psd(bytes: &[u8]) -> (u32, u32) {
-    let mut height: u32 = 0;
-    let mut width: u32 = 0;
-    height += bytes[3] as u32;
-    height += if bytes[2] > 0 {
-        bytes[2] as u32 * 256
-    } else {
-        0
-    };
-    height += if bytes[1] > 0 {
-        bytes[1] as u32 * 256 * 256
-    } else {
-        0
-    };
-    width += bytes[7] as u32;
-    width += if bytes[6] > 0 {
-        bytes[6] as u32 * 256
-    } else {
-        0
-    };
-    width += if bytes[5] > 0 {
-        bytes[5] as u32 * 256 * 256
-    } else {
-        0
-    };
-    (height, width)
+pub fn all_read_u8(
+    path: &Path,
+    mode: ImgColor,
+    apply_orientation: bool,
+    to_srgb: bool,
+    scale: u32,
+) -> Result<ArrayD<u8>, Box<dyn Error>> {
+    let img = FileBuffer::open(path).map_err(|err| Box::new(err) as Box<dyn Error>)?;
+    bytes_read_u8(&img, mode, apply_orientation, to_srgb, scale)
 }
 
-pub(crate) fn psd_gray_decode(img: &[u8]) -> Result<Array2<u8>, Box<dyn Error>> {
-    let size_bites: &[u8] = &img[14..22];
-    let color_mode = img[25];
-    let mut decoder = PSDDecoder::new(img);
-    let mut px = decoder.decode_raw().unwrap();
-    if &img[23] == &16 {
-        px = u16_to_u8(&px);
-    }
-    let (height, width) = decode_size_psd(size_bites);
-    if color_mode == 1 {
-        Ok(Array2::from_shape_vec(
-            (height as usize, width as usize),
-            px,
-        )?)
-    } else {
-        let gray = rgb8_to_gray8(&px);
-        Ok(Array2::from_shape_vec(
-            (height as usize, width as usize),
-            gray,
-        )?)
-    }
+pub fn all_read_u16(
+    path: &Path,
+    mode: ImgColor,
+    apply_orientation: bool,
+    to_srgb: bool,
+    scale: u32,
+) -> Result<ArrayD<u16>, Box<dyn Error>> {
+    let img = FileBuffer::open(path).map_err(|err| Box::new(err) as Box<dyn Error>)?;
+    bytes_read_u16(&img, mode, apply_orientation, to_srgb, scale)
 }
 
-pub(crate) fn psd_rgb_decode(img: &[u8]) -> Result<Array3<u8>, Box<dyn Error>> {
-    let size_bites: &[u8] = &img[14..22];
-    let color_mode = img[25];
-    let mut decoder = PSDDecoder::new(img);
-    let mut px = decoder.decode_raw().unwrap();
-    if &img[23] == &16 {
-        px = u16_to_u8(&px);
-    }
-    let (height, width) = decode_size_psd(size_bites);
-    if color_mode == 1 {
-        let mut rgb_values = Vec::with_capacity(px.len() * 3);
+pub fn all_read_f32(
+    path: &Path,
+    mode: ImgColor,
+    apply_orientation: bool,
+    to_srgb: bool,
+    scale: u32,
+) -> Result<ArrayD<f32>, Box<dyn Error>> {
+    let img = FileBuffer::open(path).map_err(|err| Box::new(err) as Box<dyn Error>)?;
+    bytes_read_f32(&img, mode, apply_orientation, to_srgb, scale)
+}
 
-        for gray in &px {
-            rgb_values.extend([*gray, *gray, *gray].iter().copied());
+pub fn bytes_read_u8(
+    img: &[u8],
+    mode: ImgColor,
+    apply_orientation: bool,
+    to_srgb: bool,
+    scale: u32,
+) -> Result<ArrayD<u8>, Box<dyn Error>> {
+    if matches!(mode, ImgColor::DYNAMIC) {
+        if let Some(img) =
+            native_open(img, apply_orientation, scale, |v| (v * 255.0).round() as u8)?
+        {
+            return Ok(img);
         }
-        Ok(Array3::from_shape_vec(
-            (height as usize, width as usize, 3),
-            rgb_values,
-        )?)
-    } else {
-        Ok(Array3::from_shape_vec(
-            (height as usize, width as usize, 3),
-            px,
-        )?)
     }
+    let (img, native) = img_open(img, apply_orientation, to_srgb, scale)?;
+    Ok(img_to_u8(img, native, mode))
 }
 
-pub(crate) fn psd_gray32_decode(img: &[u8]) -> Result<Array2<f32>, Box<dyn Error>> {
-    let size_bites: &[u8] = &img[14..22];
-    let color_mode = img[25];
-    let mut decoder = PSDDecoder::new(img);
-    let mut px = decoder.decode_raw().unwrap();
-    if &img[23] == &16 {
-        px = u16_to_u8(&px);
-    }
-    let (height, width) = decode_size_psd(size_bites);
-    if color_mode == 1 {
-        let px = u8_to_f32(&px);
-        Ok(Array2::from_shape_vec(
-            (height as usize, width as usize),
-            px,
-        )?)
-    } else {
-        let gray = rgb8_to_gray32(&px);
-        Ok(Array2::from_shape_vec(
-            (height as usize, width as usize),
-            gray,
-        )?)
+pub fn bytes_read_u16(
+    img: &[u8],
+    mode: ImgColor,
+    apply_orientation: bool,
+    to_srgb: bool,
+    scale: u32,
+) -> Result<ArrayD<u16>, Box<dyn Error>> {
+    if matches!(mode, ImgColor::DYNAMIC) {
+        if let Some(img) = native_open(img, apply_orientation, scale, |v| {
+            (v * 65535.0).round() as u16
+        })? {
+            return Ok(img);
+        }
     }
+    let (img, native) = img_open(img, apply_orientation, to_srgb, scale)?;
+    Ok(img_to_u16(img, native, mode))
 }
 
-pub(crate) fn psd_rgb32_decode(img: &[u8]) -> Result<Array3<f32>, Box<dyn Error>> {
-    let size_bites: &[u8] = &img[14..22];
-    let color_mode = img[25];
-    let mut decoder = PSDDecoder::new(img);
-    let mut px = decoder.decode_raw().unwrap();
-    let (height, width) = decode_size_psd(size_bites);
-    if &img[23] == &16 {
-        px = u16_to_u8(&px);
-    }
-    if color_mode == 1 {
-        let mut rgb_values: Vec<f32> = Vec::with_capacity(px.len() * 3);
-
-        for gray in &px {
-            let gray_f32 = *gray as f32 * 0.00392156862745f32;
-            rgb_values.extend([gray_f32, gray_f32, gray_f32].iter().copied());
+pub fn bytes_read_f32(
+    img: &[u8],
+    mode: ImgColor,
+    apply_orientation: bool,
+    to_srgb: bool,
+    scale: u32,
+) -> Result<ArrayD<f32>, Box<dyn Error>> {
+    if matches!(mode, ImgColor::DYNAMIC) {
+        if let Some(img) = native_open(img, apply_orientation, scale, |v| v)? {
+            return Ok(img);
         }
-        Ok(Array3::from_shape_vec(
-            (height as usize, width as usize, 3),
-            rgb_values,
-        )?)
-    } else {
-        let px = u8_to_f32(&px);
-        Ok(Array3::from_shape_vec(
-            (height as usize, width as usize, 3),
-            px,
-        )?)
     }
+    let (img, native) = img_open(img, apply_orientation, to_srgb, scale)?;
+    Ok(img_to_f32(img, native, mode))
 }
 
-pub(crate) fn psd_din_decode(img: &[u8]) -> Result<ArrayD<u8>, Box<dyn Error>> {
-    let size_bites: &[u8] = &img[14..22];
-    let channels = img[13] as usize;
-    let mut decoder = PSDDecoder::new(img);
-    let mut px = decoder.decode_raw().unwrap();
-    let (height, width) = decode_size_psd(size_bites);
-    if &img[23] == &16 {
-        px = u16_to_u8(&px);
-    }
-    if channels == 1 {
-        Ok(Array2::from_shape_vec((height as usize, width as usize), px)?.into_dyn())
-    } else {
-        Ok(Array3::from_shape_vec((height as usize, width as usize, channels), px)?.into_dyn())
-    }
+/// Reads every path with `read` on a rayon pool of `threads` workers (0 = one per core).
+///
+/// # Returns
+/// One result per path in input order, errors are kept as messages so one broken
+/// file does not fail the whole batch.
+pub fn all_read_batch<T, F>(
+    paths: &[String],
+    threads: usize,
+    read: F,
+) -> Result<Vec<Result<ArrayD<T>, String>>, ThreadPoolBuildError>
+where
+    T: Send,
+    F: Fn(&Path) -> Result<ArrayD<T>, Box<dyn Error>> + Sync,
+{
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()?;
+    Ok(pool.install(|| {
+        paths
+            .par_iter()
+            .map(|path| read(Path::new(path)).map_err(|err| err.to_string()))
+            .collect()
+    }))
 }
 
-pub(crate) fn psd_din32_decode(img: &[u8]) -> Result<ArrayD<f32>, Box<dyn Error>> {
-    let size_bites: &[u8] = &img[14..22];
-    let channels = img[13] as usize;
-    let mut decoder = PSDDecoder::new(img);
-    let px = decoder.decode_raw().unwrap();
-    let (height, width) = decode_size_psd(size_bites);
-    let px_float = match &img[23] {
-        16 => u16_to_f32(&px),
-        8 => u8_to_f32(&px),
-        _ => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("Unsupported bits: {}", &img[23]),
-            )));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 4x2 grayscale, 16 bit, raw: 0, 128, 257, 32767, 32768, 65278, 65535, 12345
+    const GRAY16_RAW: &[u8] = include_bytes!("../../../tests/fixtures/gray16_raw.psd");
+    // 2x2 rgb, 16 bit, rle: red, green, 128 gray, (16, 32, 240) in 8 bit terms
+    const RGB16_RLE: &[u8] = include_bytes!("../../../tests/fixtures/rgb16_rle.psd");
+    // 2x1 rgb + transparency, 16 bit, rle: opaque red, black at 50% matted over white
+    const RGBA16_RLE: &[u8] = include_bytes!("../../../tests/fixtures/rgba16_rle.psd");
+    // 2x1 cmyk + transparency, 8 bit, raw: no ink, 100% cyan
+    const CMYK8_RAW: &[u8] = include_bytes!("../../../tests/fixtures/cmyk8_raw.psd");
+    // 2x1 rgb + a saved selection, 8 bit, raw, no layers: (200, 100, 50), (10, 20, 30)
+    const RGB8_SELECTION: &[u8] = include_bytes!("../../../tests/fixtures/rgb8_selection.psd");
+
+    const GRAY16_VALUES: [u16; 8] = [0, 128, 257, 32767, 32768, 65278, 65535, 12345];
+
+    #[test]
+    fn psd_gray16_round_trip() {
+        let img = bytes_read_u16(GRAY16_RAW, ImgColor::DYNAMIC, false, false, 1).unwrap();
+        assert_eq!(img.shape(), &[2, 4]);
+        assert_eq!(img.iter().copied().collect::<Vec<u16>>(), GRAY16_VALUES);
+    }
+
+    #[test]
+    fn psd_gray16_to_u8() {
+        let img = bytes_read_u8(GRAY16_RAW, ImgColor::DYNAMIC, false, false, 1).unwrap();
+        assert_eq!(img.shape(), &[2, 4]);
+        assert_eq!(
+            img.iter().copied().collect::<Vec<u8>>(),
+            [0, 0, 1, 127, 128, 254, 255, 48]
+        );
+    }
+
+    #[test]
+    fn psd_gray16_to_f32() {
+        let img = bytes_read_f32(GRAY16_RAW, ImgColor::DYNAMIC, false, false, 1).unwrap();
+        for (value, expected) in img.iter().zip(GRAY16_VALUES) {
+            assert!((value - expected as f32 / 65535.0).abs() < 1e-6);
         }
-    };
-    if channels == 1 {
-        Ok(Array2::from_shape_vec((height as usize, width as usize), px_float)?.into_dyn())
-    } else {
-        Ok(
-            Array3::from_shape_vec((height as usize, width as usize, channels), px_float)?
-                .into_dyn(),
-        )
     }
-}
 
-pub fn all_read_u8(path: &Path, mode: ImgColor) -> Result<ArrayD<u8>, Box<dyn Error>> {
-    let img = FileBuffer::open(path).map_err(|err| Box::new(err) as Box<dyn Error>)?;
-    let img_magic_byte = &img[..4];
-    match img_magic_byte {
-        [56, 66, 80, 83] => match mode {
-            ImgColor::GRAY => Ok(psd_gray_decode(&img)?.into_dyn()),
-            ImgColor::RGB => Ok(psd_rgb_decode(&img)?.into_dyn()),
-            ImgColor::DYNAMIC => Ok(psd_din_decode(&img)?.into_dyn()),
-        },
-        _ => match mode {
-            ImgColor::GRAY => Ok(gray_img_open(&img)?.into_dyn()),
-            ImgColor::RGB | ImgColor::DYNAMIC => Ok(rgb_img_open(&img)?.into_dyn()),
-        },
+    #[test]
+    fn psd_rgb16_rle_to_u8() {
+        let img = bytes_read_u8(RGB16_RLE, ImgColor::RGB, false, false, 1).unwrap();
+        assert_eq!(img.shape(), &[2, 2, 3]);
+        assert_eq!(
+            img.iter().copied().collect::<Vec<u8>>(),
+            [255, 0, 0, 0, 255, 0, 128, 128, 128, 16, 32, 240]
+        );
+        let gray = bytes_read_u8(RGB16_RLE, ImgColor::GRAY, false, false, 1).unwrap();
+        assert_eq!(gray.shape(), &[2, 2]);
+        assert_eq!(gray[[1, 0]], 128);
     }
-}
 
-pub fn all_read_f32(path: &Path, mode: ImgColor) -> Result<ArrayD<f32>, Box<dyn Error>> {
-    let img = FileBuffer::open(path).map_err(|err| Box::new(err) as Box<dyn Error>)?;
-    let img_magic_byte = &img[..4];
-    match img_magic_byte {
-        [56, 66, 80, 83] => match mode {
-            ImgColor::GRAY => Ok(psd_gray32_decode(&img)?.into_dyn()),
-            ImgColor::RGB => Ok(psd_rgb32_decode(&img)?.into_dyn()),
-            ImgColor::DYNAMIC => Ok(psd_din32_decode(&img)?.into_dyn()),
-        },
-        _ => match mode {
-            ImgColor::GRAY => Ok(gray_img_openf32(&img)?.into_dyn()),
-            ImgColor::RGB | ImgColor::DYNAMIC => Ok(rgb_img_openf32(&img)?.into_dyn()),
-        },
+    #[test]
+    fn psd_rgba16_rle_unmatte() {
+        let img = bytes_read_u8(RGBA16_RLE, ImgColor::DYNAMIC, false, false, 1).unwrap();
+        assert_eq!(img.shape(), &[1, 2, 4]);
+        assert_eq!(
+            img.iter().copied().collect::<Vec<u8>>(),
+            [255, 0, 0, 255, 0, 0, 0, 128]
+        );
+    }
+
+    #[test]
+    fn psd_extra_channel_is_not_alpha() {
+        let img = bytes_read_u8(RGB8_SELECTION, ImgColor::DYNAMIC, false, false, 1).unwrap();
+        assert_eq!(img.shape(), &[1, 2, 3]);
+        assert_eq!(
+            img.iter().copied().collect::<Vec<u8>>(),
+            [200, 100, 50, 10, 20, 30]
+        );
+    }
+
+    #[test]
+    fn psd_cmyk8_native_and_rgb() {
+        let img = bytes_read_u8(CMYK8_RAW, ImgColor::DYNAMIC, false, false, 1).unwrap();
+        assert_eq!(img.shape(), &[1, 2, 5]);
+        assert_eq!(
+            img.iter().copied().collect::<Vec<u8>>(),
+            [0, 0, 0, 0, 255, 255, 0, 0, 0, 255]
+        );
+        let rgb = bytes_read_u8(CMYK8_RAW, ImgColor::RGB, false, false, 1).unwrap();
+        assert_eq!(
+            rgb.iter().copied().collect::<Vec<u8>>(),
+            [255, 255, 255, 0, 255, 255]
+        );
+    }
+
+    #[test]
+    fn psd_rejects_oversized_and_truncated() {
+        // raw psb header claiming 300001x300001 rgb with no image data
+        let mut psb = b"8BPS\0\x02\0\0\0\0\0\0\0\x03".to_vec();
+        psb.extend(300001u32.to_be_bytes());
+        psb.extend(300001u32.to_be_bytes());
+        psb.extend([0, 8, 0, 3]);
+        psb.extend([0; 16]);
+        assert!(bytes_read_u8(&psb, ImgColor::DYNAMIC, false, false, 1).is_err());
+        psb[14..22].copy_from_slice(&[0, 4, 147, 224, 0, 4, 147, 224]);
+        assert!(bytes_read_u8(&psb, ImgColor::DYNAMIC, false, false, 1).is_err());
+        let truncated = &GRAY16_RAW[..GRAY16_RAW.len() - 1];
+        assert!(bytes_read_u16(truncated, ImgColor::DYNAMIC, false, false, 1).is_err());
+        // rle psb of 300000x100 gray with 2 byte rows that can not fill it
+        let mut rle = psb[..14].to_vec();
+        rle.extend(100u32.to_be_bytes());
+        rle.extend(300000u32.to_be_bytes());
+        rle.extend([0, 8, 0, 1]);
+        rle.extend([0; 16]);
+        rle.extend([0, 1]);
+        rle.extend(2u32.to_be_bytes().repeat(100));
+        rle.extend([129, 0].repeat(100));
+        rle[12..14].copy_from_slice(&[0, 1]);
+        assert!(bytes_read_u8(&rle, ImgColor::DYNAMIC, false, false, 1).is_err());
     }
 }
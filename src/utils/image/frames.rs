@@ -0,0 +1,348 @@
+use std::error::Error;
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom};
+
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::tiff::TiffDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::{AnimationDecoder, DynamicImage, ImageFormat};
+
+use crate::utils::image::decode::img_open;
+use crate::utils::image::metadata::png_chunk;
+
+// Frame access for the files image decoders read as a single image: the pages of
+// a multi-page tiff and the frames of animated gif, webp and png.
+
+pub struct Frame {
+    pub image: DynamicImage,
+    /// Native channel count like `img_open` returns it.
+    pub channels: u8,
+    /// Display time in milliseconds, 0 for tiff pages and still images.
+    pub delay: u32,
+}
+
+/// Offsets of the ifds of a tiff file: one per page.
+fn tiff_pages(bytes: &[u8]) -> Option<Vec<u64>> {
+    let big_endian = match bytes.get(..2)? {
+        b"II" => false,
+        b"MM" => true,
+        _ => return None,
+    };
+    let read = |pos: u64, len: usize| -> Option<u64> {
+        let pos = usize::try_from(pos).ok()?;
+        let data = bytes.get(pos..pos.checked_add(len)?)?;
+        let mut value = 0u64;
+        for i in 0..len {
+            let byte = if big_endian {
+                data[i]
+            } else {
+                data[len - 1 - i]
+            };
+            value = value << 8 | byte as u64;
+        }
+        Some(value)
+    };
+    // classic tiff has 2 byte entry counts, 12 byte entries and 4 byte offsets
+    let (count_len, entry_len, offset_len, first) = match read(2, 2)? {
+        42 => (2, 12, 4, 4),
+        43 => (8, 20, 8, 8),
+        _ => return None,
+    };
+    let mut pages = Vec::new();
+    let mut ifd = read(first, offset_len)?;
+    while ifd != 0 && !pages.contains(&ifd) {
+        pages.push(ifd);
+        let entries = read(ifd, count_len)?;
+        // the next offset follows the entries, a count past the end of the file ends the walk
+        let next = entries
+            .checked_mul(entry_len)
+            .and_then(|len| len.checked_add(count_len as u64))
+            .and_then(|len| len.checked_add(ifd));
+        ifd = next.and_then(|next| read(next, offset_len)).unwrap_or(0);
+    }
+    Some(pages)
+}
+
+/// Reader over a tiff file whose header points to another ifd, so the image crate
+/// decodes that page as the first one.
+struct TiffPage<'a> {
+    bytes: &'a [u8],
+    header: Vec<u8>,
+    pos: u64,
+}
+
+impl<'a> TiffPage<'a> {
+    fn new(bytes: &'a [u8], ifd: u64) -> TiffPage<'a> {
+        let big_endian = bytes.starts_with(b"MM");
+        // bigtiff keeps the first ifd offset as 8 bytes after the offset size
+        let (at, len) = match bytes.get(2..4) {
+            Some([0, 43] | [43, 0]) => (8, 8),
+            _ => (4, 4),
+        };
+        let mut header = bytes[..at + len].to_vec();
+        let offset = if big_endian {
+            ifd.to_be_bytes()[8 - len..].to_vec()
+        } else {
+            ifd.to_le_bytes()[..len].to_vec()
+        };
+        header[at..].copy_from_slice(&offset);
+        TiffPage {
+            bytes,
+            header,
+            pos: 0,
+        }
+    }
+}
+
+impl Read for TiffPage<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let pos = self.pos as usize;
+        let source = if pos < self.header.len() {
+            &self.header[pos..]
+        } else {
+            self.bytes.get(pos..).unwrap_or(&[])
+        };
+        let len = source.len().min(buf.len());
+        buf[..len].copy_from_slice(&source[..len]);
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl Seek for TiffPage<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(offset) => (self.bytes.len() as u64).checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+        self.pos =
+            pos.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid seek"))?;
+        Ok(self.pos)
+    }
+}
+
+/// Number of image descriptors in a gif.
+fn gif_frames(bytes: &[u8]) -> Option<usize> {
+    let flags = *bytes.get(10)?;
+    let mut pos = 13;
+    if flags & 0x80 != 0 {
+        pos += 3 << ((flags & 7) + 1);
+    }
+    let skip_blocks = |mut pos: usize| -> Option<usize> {
+        loop {
+            let len = *bytes.get(pos)? as usize;
+            pos += 1 + len;
+            if len == 0 {
+                return Some(pos);
+            }
+        }
+    };
+    let mut frames = 0;
+    loop {
+        match *bytes.get(pos)? {
+            0x2C => {
+                frames += 1;
+                let flags = *bytes.get(pos + 9)?;
+                pos += 10;
+                if flags & 0x80 != 0 {
+                    pos += 3 << ((flags & 7) + 1);
+                }
+                // lzw minimum code size
+                pos = skip_blocks(pos + 1)?;
+            }
+            0x21 => pos = skip_blocks(pos + 2)?,
+            _ => return Some(frames),
+        }
+    }
+}
+
+/// Number of ANMF chunks of an animated webp.
+fn webp_frames(bytes: &[u8]) -> usize {
+    let mut pos = 12;
+    let mut frames = 0;
+    while let Some(header) = bytes.get(pos..pos + 8) {
+        if &header[..4] == b"ANMF" {
+            frames += 1;
+        }
+        let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        pos += 8 + len + len % 2;
+    }
+    frames.max(1)
+}
+
+/// Number of frames or pages without decoding the pixels, 1 for still images.
+pub fn frame_count(bytes: &[u8]) -> Result<usize, Box<dyn Error>> {
+    if let Some(pages) = tiff_pages(bytes) {
+        return Ok(pages.len());
+    }
+    Ok(match image::guess_format(bytes).ok() {
+        Some(ImageFormat::Gif) => gif_frames(bytes).ok_or("invalid gif")?,
+        Some(ImageFormat::WebP) => webp_frames(bytes),
+        Some(ImageFormat::Png) => png_chunk(bytes, b"acTL")
+            .and_then(|actl| actl.get(..4))
+            .map_or(1, |frames| {
+                u32::from_be_bytes([frames[0], frames[1], frames[2], frames[3]]) as usize
+            }),
+        _ => 1,
+    })
+}
+
+fn animation_frames<'a>(
+    frames: image::Frames<'a>,
+    index: Option<usize>,
+) -> Result<Vec<Frame>, Box<dyn Error>> {
+    let frames = frames
+        .skip(index.unwrap_or(0))
+        .take(if index.is_some() { 1 } else { usize::MAX });
+    let frames = frames
+        .map(|frame| {
+            let frame = frame?;
+            let (numer, denom) = frame.delay().numer_denom_ms();
+            Ok(Frame {
+                delay: (numer + denom / 2).checked_div(denom).unwrap_or(0),
+                image: DynamicImage::ImageRgba8(frame.into_buffer()),
+                channels: 4,
+            })
+        })
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    match (index, frames.is_empty()) {
+        (Some(index), true) => Err(format!("frame {} out of range", index).into()),
+        _ => Ok(frames),
+    }
+}
+
+/// Decodes one frame (`index`) or all frames of a multi-page tiff or an animated
+/// gif, webp or png, animation frames are composited to the full canvas.
+///
+/// Still images have a single frame decoded like `img_open`.
+pub fn read_frames(bytes: &[u8], index: Option<usize>) -> Result<Vec<Frame>, Box<dyn Error>> {
+    // also bigtiff, which the format guess of the image crate does not know
+    if let Some(pages) = tiff_pages(bytes) {
+        let pages = match index {
+            Some(index) => vec![*pages
+                .get(index)
+                .ok_or_else(|| format!("frame {} out of range, {} pages", index, pages.len()))?],
+            None => pages,
+        };
+        return pages
+            .into_iter()
+            .map(|ifd| {
+                let decoder = TiffDecoder::new(BufReader::new(TiffPage::new(bytes, ifd)))?;
+                let image = DynamicImage::from_decoder(decoder)?;
+                let channels = image.color().channel_count();
+                Ok(Frame {
+                    image,
+                    channels,
+                    delay: 0,
+                })
+            })
+            .collect();
+    }
+    match image::guess_format(bytes).ok() {
+        Some(ImageFormat::Gif) => {
+            animation_frames(GifDecoder::new(Cursor::new(bytes))?.into_frames(), index)
+        }
+        Some(ImageFormat::WebP) => {
+            let decoder = WebPDecoder::new(Cursor::new(bytes))?;
+            if decoder.has_animation() {
+                animation_frames(decoder.into_frames(), index)
+            } else {
+                still_frame(bytes, index)
+            }
+        }
+        Some(ImageFormat::Png) if png_chunk(bytes, b"acTL").is_some() => {
+            let decoder = PngDecoder::new(Cursor::new(bytes))?.apng()?;
+            animation_frames(decoder.into_frames(), index)
+        }
+        _ => still_frame(bytes, index),
+    }
+}
+
+fn still_frame(bytes: &[u8], index: Option<usize>) -> Result<Vec<Frame>, Box<dyn Error>> {
+    if let Some(index @ 1..) = index {
+        return Err(format!("frame {} out of range, 1 frame", index).into());
+    }
+    let (image, channels) = img_open(bytes, false, false, 1)?;
+    Ok(vec![Frame {
+        image,
+        channels,
+        delay: 0,
+    }])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiff::encoder::{colortype, TiffEncoder, TiffKind};
+
+    fn write_pages<K: TiffKind>(mut encoder: TiffEncoder<&mut Cursor<Vec<u8>>, K>) {
+        // page n is n+1 pixels wide, filled with 10 * n
+        for page in 0..3u8 {
+            let width = page as u32 + 1;
+            let px = vec![page * 10; width as usize * 2 * 3];
+            encoder
+                .write_image::<colortype::RGB8>(width, 2, &px)
+                .unwrap();
+        }
+    }
+
+    fn check_pages(bytes: &[u8]) {
+        assert_eq!(frame_count(bytes).unwrap(), 3);
+        let frames = read_frames(bytes, None).unwrap();
+        for (page, frame) in frames.iter().enumerate() {
+            assert_eq!(frame.image.width(), page as u32 + 1);
+            assert_eq!(frame.channels, 3);
+            assert_eq!(
+                frame.image.to_rgb8().get_pixel(0, 1).0,
+                [page as u8 * 10; 3]
+            );
+        }
+        let last = read_frames(bytes, Some(2)).unwrap();
+        assert_eq!(last[0].image.width(), 3);
+        assert!(read_frames(bytes, Some(3)).is_err());
+    }
+
+    #[test]
+    fn tiff_pages_classic_and_big() {
+        let mut tiff = Cursor::new(Vec::new());
+        write_pages(TiffEncoder::new(&mut tiff).unwrap());
+        check_pages(tiff.get_ref());
+
+        let mut big = Cursor::new(Vec::new());
+        write_pages(TiffEncoder::new_big(&mut big).unwrap());
+        check_pages(big.get_ref());
+
+        // a bigtiff ifd claiming u64::MAX entries ends the walk after its page
+        let mut bytes = b"II\x2b\0\x08\0\0\0\x10\0\0\0\0\0\0\0".to_vec();
+        bytes.extend(u64::MAX.to_le_bytes());
+        assert_eq!(tiff_pages(&bytes), Some(vec![16]));
+    }
+
+    #[test]
+    fn gif_frames_with_delays() {
+        use image::codecs::gif::GifEncoder;
+        use image::{Delay, RgbaImage};
+
+        let mut gif = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut gif);
+            for (shade, delay) in [(0u8, 40u32), (200, 100)] {
+                let image = RgbaImage::from_pixel(2, 2, image::Rgba([shade, shade, shade, 255]));
+                let delay = Delay::from_numer_denom_ms(delay, 1);
+                encoder
+                    .encode_frame(image::Frame::from_parts(image, 0, 0, delay))
+                    .unwrap();
+            }
+        }
+        assert_eq!(frame_count(&gif).unwrap(), 2);
+        let frames = read_frames(&gif, None).unwrap();
+        let delays: Vec<u32> = frames.iter().map(|frame| frame.delay).collect();
+        assert_eq!(delays, [40, 100]);
+        assert_eq!(
+            frames[1].image.to_rgba8().get_pixel(1, 1).0,
+            [200, 200, 200, 255]
+        );
+        assert!(read_frames(&gif, Some(2)).is_err());
+    }
+}
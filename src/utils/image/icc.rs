@@ -0,0 +1,158 @@
+use std::error::Error;
+use std::io::Cursor;
+
+use image::{DynamicImage, ImageBuffer, ImageDecoder, Luma, LumaA, Rgb, Rgba};
+use moxcms::{ColorProfile, DataColorSpace, Layout, TransformOptions};
+
+use crate::utils::image::psd::{PsdFile, PSD_MAGIC};
+
+/// Image resource of the embedded icc profile in psd files.
+pub(crate) const PSD_ICC_PROFILE: u16 = 1039;
+
+/// Reads the embedded icc profile: the psd image resource or whatever the image
+/// crate decoder exposes (png iCCP, jpeg APP2, tiff, webp).
+pub(crate) fn icc_profile(bytes: &[u8]) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    if bytes.starts_with(&PSD_MAGIC) {
+        let psd = PsdFile::parse(bytes)?;
+        return Ok(psd.resource(PSD_ICC_PROFILE).map(<[u8]>::to_vec));
+    }
+    let mut decoder = image::ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()?
+        .into_decoder()?;
+    Ok(decoder.icc_profile().ok().flatten())
+}
+
+/// sRGB as gray profile: the sRGB tone curve on the D50 white point.
+fn srgb_gray() -> ColorProfile {
+    let srgb = ColorProfile::new_srgb();
+    let mut gray = ColorProfile::new_gray_with_gamma(2.2);
+    gray.gray_trc = srgb.red_trc.clone();
+    gray
+}
+
+/// Converts the pixels from the embedded `icc` profile to sRGB (gray images to the
+/// sRGB tone curve).
+///
+/// The image is returned unchanged when the profile can not be parsed or does not
+/// describe the color space of the decoded samples.
+pub(crate) fn to_srgb(img: DynamicImage, icc: &[u8]) -> Result<DynamicImage, Box<dyn Error>> {
+    let Ok(profile) = ColorProfile::new_from_slice(icc) else {
+        return Ok(img);
+    };
+    let gray = !img.color().has_color();
+    let matches = match profile.color_space {
+        DataColorSpace::Rgb => !gray,
+        DataColorSpace::Gray => gray,
+        _ => false,
+    };
+    if !matches {
+        return Ok(img);
+    }
+    let target = if gray {
+        srgb_gray()
+    } else {
+        ColorProfile::new_srgb()
+    };
+    let options = TransformOptions::default();
+    let (width, height) = (img.width(), img.height());
+
+    macro_rules! convert {
+        ($buf:expr, $create:ident, $layout:expr, $variant:ident, $pixel:ty) => {{
+            let src = $buf.into_raw();
+            let mut dst = vec![Default::default(); src.len()];
+            profile
+                .$create($layout, &target, $layout, options)?
+                .transform(&src, &mut dst)?;
+            DynamicImage::$variant(
+                ImageBuffer::<$pixel, _>::from_raw(width, height, dst).ok_or("buffer size")?,
+            )
+        }};
+    }
+    Ok(match img {
+        DynamicImage::ImageLuma8(buf) => {
+            convert!(
+                buf,
+                create_transform_8bit,
+                Layout::Gray,
+                ImageLuma8,
+                Luma<u8>
+            )
+        }
+        DynamicImage::ImageLumaA8(buf) => {
+            convert!(
+                buf,
+                create_transform_8bit,
+                Layout::GrayAlpha,
+                ImageLumaA8,
+                LumaA<u8>
+            )
+        }
+        DynamicImage::ImageRgb8(buf) => {
+            convert!(buf, create_transform_8bit, Layout::Rgb, ImageRgb8, Rgb<u8>)
+        }
+        DynamicImage::ImageRgba8(buf) => {
+            convert!(
+                buf,
+                create_transform_8bit,
+                Layout::Rgba,
+                ImageRgba8,
+                Rgba<u8>
+            )
+        }
+        DynamicImage::ImageLuma16(buf) => {
+            convert!(
+                buf,
+                create_transform_16bit,
+                Layout::Gray,
+                ImageLuma16,
+                Luma<u16>
+            )
+        }
+        DynamicImage::ImageLumaA16(buf) => {
+            convert!(
+                buf,
+                create_transform_16bit,
+                Layout::GrayAlpha,
+                ImageLumaA16,
+                LumaA<u16>
+            )
+        }
+        DynamicImage::ImageRgb16(buf) => {
+            convert!(
+                buf,
+                create_transform_16bit,
+                Layout::Rgb,
+                ImageRgb16,
+                Rgb<u16>
+            )
+        }
+        DynamicImage::ImageRgba16(buf) => {
+            convert!(
+                buf,
+                create_transform_16bit,
+                Layout::Rgba,
+                ImageRgba16,
+                Rgba<u16>
+            )
+        }
+        DynamicImage::ImageRgb32F(buf) => {
+            convert!(
+                buf,
+                create_transform_f32,
+                Layout::Rgb,
+                ImageRgb32F,
+                Rgb<f32>
+            )
+        }
+        DynamicImage::ImageRgba32F(buf) => {
+            convert!(
+                buf,
+                create_transform_f32,
+                Layout::Rgba,
+                ImageRgba32F,
+                Rgba<f32>
+            )
+        }
+        img => img,
+    })
+}
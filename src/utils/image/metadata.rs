@@ -0,0 +1,138 @@
+// Header level parsers for the metadata the image crate does not expose:
+// resolution, exif orientation, jpeg components and png color type.
+
+pub(crate) struct TiffTags {
+    pub orientation: Option<u8>,
+    pub dpi: Option<(f32, f32)>,
+}
+
+/// Reads orientation and resolution from the first ifd of a tiff structure,
+/// this is both a tiff file and an exif block.
+pub(crate) fn tiff_tags(data: &[u8]) -> Option<TiffTags> {
+    let data = data.strip_prefix(b"Exif\0\0").unwrap_or(data);
+    let big_endian = match data.get(..4)? {
+        [73, 73, 42, 0] => false,
+        [77, 77, 0, 42] => true,
+        _ => return None,
+    };
+    let u16_at = |pos: usize| -> Option<u16> {
+        let bytes = [*data.get(pos)?, *data.get(pos + 1)?];
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let u32_at = |pos: usize| -> Option<u32> {
+        let bytes: [u8; 4] = data.get(pos..pos + 4)?.try_into().ok()?;
+        Some(if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    };
+    let rational_at = |pos: usize| -> Option<f32> {
+        let offset = u32_at(pos)? as usize;
+        let denominator = u32_at(offset + 4)?;
+        (denominator != 0).then(|| u32_at(offset).unwrap_or(0) as f32 / denominator as f32)
+    };
+
+    let ifd = u32_at(4)? as usize;
+    let mut tags = TiffTags {
+        orientation: None,
+        dpi: None,
+    };
+    let (mut x_res, mut y_res, mut unit) = (None, None, 2);
+    for i in 0..u16_at(ifd)? as usize {
+        let entry = ifd + 2 + i * 12;
+        let value = entry + 8;
+        match u16_at(entry)? {
+            274 => tags.orientation = u16_at(value).map(|v| v.min(255) as u8),
+            282 => x_res = rational_at(value),
+            283 => y_res = rational_at(value),
+            296 => unit = u16_at(value)?,
+            _ => {}
+        }
+    }
+    tags.dpi = match (x_res, y_res, unit) {
+        (Some(x), Some(y), 2) => Some((x, y)),
+        (Some(x), Some(y), 3) => Some((x * 2.54, y * 2.54)),
+        _ => None,
+    };
+    Some(tags)
+}
+
+/// Finds a chunk of a png file before the pixel data.
+pub(crate) fn png_chunk<'a>(bytes: &'a [u8], name: &[u8; 4]) -> Option<&'a [u8]> {
+    let mut pos = 8;
+    while let Some(header) = bytes.get(pos..pos + 8) {
+        let len = u32::from_be_bytes(header[..4].try_into().ok()?) as usize;
+        if &header[4..] == name {
+            return bytes.get(pos + 8..pos + 8 + len);
+        }
+        if &header[4..] == b"IDAT" {
+            return None;
+        }
+        pos += 12 + len;
+    }
+    None
+}
+
+pub(crate) fn png_dpi(bytes: &[u8]) -> Option<(f32, f32)> {
+    let phys = png_chunk(bytes, b"pHYs")?;
+    if phys.len() < 9 || phys[8] != 1 {
+        return None;
+    }
+    let x = u32::from_be_bytes(phys[..4].try_into().ok()?);
+    let y = u32::from_be_bytes(phys[4..8].try_into().ok()?);
+    // pixels per meter
+    Some((x as f32 * 0.0254, y as f32 * 0.0254))
+}
+
+/// Returns the segments of a jpeg file up to the start of scan.
+pub(crate) fn jpeg_segments(bytes: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut segments = Vec::new();
+    let mut pos = 2;
+    while let Some([0xFF, marker, hi, lo]) = bytes.get(pos..pos + 4) {
+        let (marker, len) = (*marker, u16::from_be_bytes([*hi, *lo]) as usize);
+        if marker == 0xDA || marker == 0xD9 || len < 2 {
+            break;
+        }
+        match bytes.get(pos + 4..pos + 2 + len) {
+            Some(data) => segments.push((marker, data)),
+            None => break,
+        }
+        pos += 2 + len;
+    }
+    segments
+}
+
+pub(crate) fn jpeg_components(bytes: &[u8]) -> Option<(u8, u8)> {
+    // (precision, components) of the frame header
+    jpeg_segments(bytes)
+        .into_iter()
+        .find(|(marker, data)| {
+            matches!(marker, 0xC0..=0xCF) && ![0xC4, 0xC8, 0xCC].contains(marker) && data.len() >= 6
+        })
+        .map(|(_, data)| (data[0], data[5]))
+}
+
+pub(crate) fn jpeg_dpi(bytes: &[u8]) -> Option<(f32, f32)> {
+    let segments = jpeg_segments(bytes);
+    let jfif = segments
+        .iter()
+        .find(|(marker, data)| *marker == 0xE0 && data.starts_with(b"JFIF\0") && data.len() >= 12);
+    if let Some((_, data)) = jfif {
+        let x = u16::from_be_bytes([data[8], data[9]]) as f32;
+        let y = u16::from_be_bytes([data[10], data[11]]) as f32;
+        match data[7] {
+            1 => return Some((x, y)),
+            2 => return Some((x * 2.54, y * 2.54)),
+            _ => {}
+        }
+    }
+    segments
+        .iter()
+        .find(|(marker, data)| *marker == 0xE1 && data.starts_with(b"Exif\0\0"))
+        .and_then(|(_, data)| tiff_tags(data)?.dpi)
+}
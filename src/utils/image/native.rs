@@ -0,0 +1,331 @@
+use std::error::Error;
+use std::iter::once;
+
+use image::metadata::Orientation;
+use image::DynamicImage;
+use moxcms::{ColorProfile, DataColorSpace, Layout, TransformOptions};
+use ndarray::{ArrayD, Axis, IxDyn};
+use zune_core::bytestream::ZCursor;
+use zune_core::colorspace::ColorSpace;
+use zune_core::options::DecoderOptions;
+use zune_jpeg::JpegDecoder;
+
+use crate::utils::core::convert::buffer;
+use crate::utils::core::cvt_color_float::cmyk2rgb;
+use crate::utils::image::metadata::jpeg_components;
+use crate::utils::image::psd::gray_to_rgb;
+use crate::utils::image::region::Region;
+
+// Color spaces `DynamicImage` has no room for (cmyk, lab and psd multichannel).
+// DYNAMIC reads return the samples as decoded, every other read converts them to rgb.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum NativeSpace {
+    Cmyk,
+    Lab,
+    Multichannel,
+}
+
+pub(crate) struct NativeImage {
+    pub width: usize,
+    pub height: usize,
+    pub space: NativeSpace,
+    /// Channels that carry color, a transparency channel follows them with `alpha`.
+    pub color_channels: usize,
+    pub alpha: bool,
+    /// Source bit depth (8, 16 or 32), the rgb conversion keeps it.
+    pub depth: u16,
+    /// Interleaved samples in 0-1: cmyk as ink amounts (0 = no ink), lab as stored
+    /// (L / 100, (a + 128) / 255, (b + 128) / 255), multichannel as stored.
+    pub samples: Vec<f32>,
+}
+
+impl NativeImage {
+    pub fn channels(&self) -> usize {
+        self.color_channels + self.alpha as usize
+    }
+
+    /// Samples as a (height, width, channels) array, turned upright by `orientation`.
+    pub fn to_array<T: Clone>(
+        &self,
+        orientation: Option<Orientation>,
+        sample: impl Fn(f32) -> T,
+    ) -> Result<ArrayD<T>, Box<dyn Error>> {
+        let mut shape = vec![self.height, self.width, self.channels()];
+        if shape[2] == 1 {
+            shape.pop();
+        }
+        let array = ArrayD::from_shape_vec(
+            IxDyn(&shape),
+            self.samples.iter().map(|&v| sample(v)).collect(),
+        )?;
+        Ok(match orientation {
+            Some(orientation) => orient(array, orientation),
+            None => array,
+        })
+    }
+
+    /// Cuts out the `region` rectangle.
+    pub fn crop(&self, region: Region) -> Result<NativeImage, Box<dyn Error>> {
+        region.check(self.width, self.height)?;
+        let channels = self.channels();
+        let samples = region
+            .rows()
+            .flat_map(|y| {
+                let row = (y * self.width + region.x) * channels;
+                &self.samples[row..row + region.width * channels]
+            })
+            .copied()
+            .collect();
+        Ok(NativeImage {
+            width: region.width,
+            height: region.height,
+            samples,
+            ..*self
+        })
+    }
+
+    /// Averages `scale` x `scale` pixel blocks, the blocks at the right and bottom
+    /// edges average what is left of them.
+    pub fn downscale(self, scale: u32) -> NativeImage {
+        let scale = scale as usize;
+        if scale <= 1 {
+            return self;
+        }
+        let channels = self.channels();
+        let (width, height) = (self.width.div_ceil(scale), self.height.div_ceil(scale));
+        let mut samples = vec![0.0; width * height * channels];
+        for y in 0..height {
+            let rows = y * scale..((y + 1) * scale).min(self.height);
+            for x in 0..width {
+                let cols = x * scale..((x + 1) * scale).min(self.width);
+                let out = &mut samples[(y * width + x) * channels..][..channels];
+                for sy in rows.clone() {
+                    for sx in cols.clone() {
+                        let pixel = &self.samples[(sy * self.width + sx) * channels..][..channels];
+                        out.iter_mut().zip(pixel).for_each(|(o, p)| *o += p);
+                    }
+                }
+                let count = (rows.len() * cols.len()) as f32;
+                out.iter_mut().for_each(|o| *o /= count);
+            }
+        }
+        NativeImage {
+            width,
+            height,
+            samples,
+            ..self
+        }
+    }
+
+    /// Converts to rgb (gray for a single multichannel channel) in the source bit depth,
+    /// cmyk goes through the embedded `icc` profile when it is a cmyk profile.
+    pub fn to_dynamic(&self, icc: Option<&[u8]>) -> Result<DynamicImage, Box<dyn Error>> {
+        let channels = self.channels();
+        let colors = self.color_channels;
+        let color: Vec<f32> = if self.alpha {
+            self.samples
+                .chunks_exact(channels)
+                .flat_map(|pixel| pixel[..colors].iter().copied())
+                .collect()
+        } else {
+            self.samples.clone()
+        };
+        let (px, out) = match self.space {
+            NativeSpace::Cmyk => {
+                let rgb = icc
+                    .and_then(|icc| cmyk_profile_to_srgb(&color, icc))
+                    .unwrap_or_else(|| cmyk2rgb(&color));
+                (rgb, 3)
+            }
+            NativeSpace::Lab => (color.chunks_exact(3).flat_map(lab_to_srgb).collect(), 3),
+            NativeSpace::Multichannel if colors >= 3 => (
+                color
+                    .chunks_exact(colors)
+                    .flat_map(|pixel| pixel[..3].iter().copied())
+                    .collect(),
+                3,
+            ),
+            NativeSpace::Multichannel => (
+                color.chunks_exact(colors).map(|pixel| pixel[0]).collect(),
+                1,
+            ),
+        };
+        let px: Vec<f32> = if self.alpha {
+            px.chunks_exact(out)
+                .zip(self.samples.chunks_exact(channels))
+                .flat_map(|(color, pixel)| color.iter().copied().chain(once(pixel[colors])))
+                .collect()
+        } else {
+            px
+        };
+        let out = out + self.alpha as usize;
+        let (w, h) = (self.width as u32, self.height as u32);
+        let to_u8 = |px: Vec<f32>| -> Vec<u8> {
+            px.into_iter()
+                .map(|v| (v * 255.0).round().clamp(0.0, 255.0) as u8)
+                .collect()
+        };
+        let to_u16 = |px: Vec<f32>| -> Vec<u16> {
+            px.into_iter()
+                .map(|v| (v * 65535.0).round().clamp(0.0, 65535.0) as u16)
+                .collect()
+        };
+        Ok(match (self.depth, out) {
+            (8, 1) => DynamicImage::ImageLuma8(buffer(w, h, to_u8(px))?),
+            (8, 2) => DynamicImage::ImageLumaA8(buffer(w, h, to_u8(px))?),
+            (8, 3) => DynamicImage::ImageRgb8(buffer(w, h, to_u8(px))?),
+            (8, _) => DynamicImage::ImageRgba8(buffer(w, h, to_u8(px))?),
+            (16, 1) => DynamicImage::ImageLuma16(buffer(w, h, to_u16(px))?),
+            (16, 2) => DynamicImage::ImageLumaA16(buffer(w, h, to_u16(px))?),
+            (16, 3) => DynamicImage::ImageRgb16(buffer(w, h, to_u16(px))?),
+            (16, _) => DynamicImage::ImageRgba16(buffer(w, h, to_u16(px))?),
+            (_, 1 | 2) => {
+                let rgb = gray_to_rgb(&px, out);
+                match out {
+                    1 => DynamicImage::ImageRgb32F(buffer(w, h, rgb)?),
+                    _ => DynamicImage::ImageRgba32F(buffer(w, h, rgb)?),
+                }
+            }
+            (_, 3) => DynamicImage::ImageRgb32F(buffer(w, h, px)?),
+            (_, _) => DynamicImage::ImageRgba32F(buffer(w, h, px)?),
+        })
+    }
+}
+
+/// Decodes a cmyk (or adobe ycck) jpeg without the rgb conversion of the image crate.
+///
+/// # Returns
+/// None for anything that is not a cmyk jpeg.
+pub(crate) fn cmyk_jpeg(bytes: &[u8]) -> Result<Option<NativeImage>, Box<dyn Error>> {
+    if !bytes.starts_with(&[0xFF, 0xD8]) || jpeg_components(bytes).is_none_or(|(_, c)| c != 4) {
+        return Ok(None);
+    }
+    // the default size limits of zune bound the allocation
+    let options = DecoderOptions::default().set_strict_mode(false);
+    let mut decoder = JpegDecoder::new_with_options(ZCursor::new(bytes), options);
+    decoder.decode_headers()?;
+    let space = match decoder.input_colorspace() {
+        Some(space @ (ColorSpace::CMYK | ColorSpace::YCCK)) => space,
+        _ => return Ok(None),
+    };
+    let (width, height) = decoder.dimensions().ok_or("jpeg has no frame header")?;
+    // same colorspace in and out copies the components as stored
+    let options = options.jpeg_set_out_colorspace(space);
+    let px = JpegDecoder::new_with_options(ZCursor::new(bytes), options).decode()?;
+    // adobe jpegs store cmyk inverted (255 = no ink), ycck holds the inverted cmy as ycbcr
+    let samples = px
+        .chunks_exact(4)
+        .flat_map(|pixel| {
+            let k = 1.0 - pixel[3] as f32 / 255.0;
+            match space {
+                ColorSpace::YCCK => {
+                    let [c, m, y] = ycc_to_rgb(pixel[0], pixel[1], pixel[2]);
+                    [c, m, y, k]
+                }
+                _ => [
+                    1.0 - pixel[0] as f32 / 255.0,
+                    1.0 - pixel[1] as f32 / 255.0,
+                    1.0 - pixel[2] as f32 / 255.0,
+                    k,
+                ],
+            }
+        })
+        .collect();
+    Ok(Some(NativeImage {
+        width,
+        height,
+        space: NativeSpace::Cmyk,
+        color_channels: 4,
+        alpha: false,
+        depth: 8,
+        samples,
+    }))
+}
+
+fn ycc_to_rgb(y: u8, cb: u8, cr: u8) -> [f32; 3] {
+    // jfif full range ycbcr
+    let (y, cb, cr) = (y as f32, cb as f32 - 128.0, cr as f32 - 128.0);
+    [
+        (y + 1.402 * cr) / 255.0,
+        (y - 0.344136 * cb - 0.714136 * cr) / 255.0,
+        (y + 1.772 * cb) / 255.0,
+    ]
+    .map(|v| v.clamp(0.0, 1.0))
+}
+
+fn cmyk_profile_to_srgb(cmyk: &[f32], icc: &[u8]) -> Option<Vec<f32>> {
+    let profile = ColorProfile::new_from_slice(icc).ok()?;
+    if profile.color_space != DataColorSpace::Cmyk {
+        return None;
+    }
+    // moxcms takes 4 channel cmyk in the rgba layout
+    let transform = profile
+        .create_transform_f32(
+            Layout::Rgba,
+            &ColorProfile::new_srgb(),
+            Layout::Rgb,
+            TransformOptions::default(),
+        )
+        .ok()?;
+    let mut rgb = vec![0.0; cmyk.len() / 4 * 3];
+    transform.transform(cmyk, &mut rgb).ok()?;
+    Some(rgb)
+}
+
+/// CIE Lab on the D50 white point (as photoshop stores it) to sRGB.
+fn lab_to_srgb(lab: &[f32]) -> [f32; 3] {
+    let l = lab[0] * 100.0;
+    let a = lab[1] * 255.0 - 128.0;
+    let b = lab[2] * 255.0 - 128.0;
+    let fy = (l + 16.0) / 116.0;
+    let finv = |t: f32| {
+        if t > 6.0 / 29.0 {
+            t * t * t
+        } else {
+            3.0 * (6.0f32 / 29.0).powi(2) * (t - 4.0 / 29.0)
+        }
+    };
+    let x = 0.9642 * finv(fy + a / 500.0);
+    let y = finv(fy);
+    let z = 0.8249 * finv(fy - b / 200.0);
+    // XYZ (D50) to linear sRGB with the bradford adaptation to D65 folded in
+    let linear = [
+        3.133856 * x - 1.616867 * y - 0.490615 * z,
+        -0.978768 * x + 1.916142 * y + 0.033454 * z,
+        0.071945 * x - 0.228991 * y + 1.405243 * z,
+    ];
+    linear.map(|c| {
+        let c = c.clamp(0.0, 1.0);
+        if c <= 0.0031308 {
+            c * 12.92
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        }
+    })
+}
+
+/// Applies an exif orientation to a (height, width, ...) array.
+fn orient<T: Clone>(array: ArrayD<T>, orientation: Orientation) -> ArrayD<T> {
+    let mut array = array;
+    let (transpose, flip_y, flip_x) = match orientation {
+        Orientation::NoTransforms => return array,
+        Orientation::Rotate90 => (true, false, true),
+        Orientation::Rotate180 => (false, true, true),
+        Orientation::Rotate270 => (true, true, false),
+        Orientation::FlipHorizontal => (false, false, true),
+        Orientation::FlipVertical => (false, true, false),
+        Orientation::Rotate90FlipH => (true, false, false),
+        Orientation::Rotate270FlipH => (true, true, true),
+    };
+    if transpose {
+        array.swap_axes(0, 1);
+    }
+    if flip_y {
+        array.invert_axis(Axis(0));
+    }
+    if flip_x {
+        array.invert_axis(Axis(1));
+    }
+    array.as_standard_layout().into_owned()
+}
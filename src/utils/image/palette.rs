@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+
+/// Reduces the colors of the image to at most `colors` entries with median cut.
+///
+/// Images that already have few enough colors get an exact palette. Pixels are rgb or
+/// rgba, alpha is split like the color channels.
+pub(crate) fn median_cut<const N: usize>(pixels: &[[u8; N]], colors: usize) -> Vec<[u8; N]> {
+    let mut histogram: HashMap<[u8; N], u32> = HashMap::new();
+    for px in pixels {
+        *histogram.entry(*px).or_insert(0) += 1;
+    }
+    let mut entries: Vec<([u8; N], u32)> = histogram.into_iter().collect();
+    entries.sort_unstable();
+    if entries.len() <= colors {
+        return entries.into_iter().map(|(color, _)| color).collect();
+    }
+
+    let mut boxes = vec![entries];
+    while boxes.len() < colors {
+        // split the box with the widest channel range
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .map(|(i, colors)| {
+                let (channel, range) = channel_range(colors);
+                (range, i, channel)
+            })
+            .max();
+        let Some((_, i, channel)) = widest else {
+            break;
+        };
+        let mut colors = boxes.swap_remove(i);
+        colors.sort_unstable_by_key(|(color, _)| color[channel]);
+        // split at the median of the pixel counts
+        let total: u64 = colors.iter().map(|(_, count)| *count as u64).sum();
+        let mut seen = 0;
+        let mut split = colors.len() / 2;
+        for (pos, (_, count)) in colors.iter().enumerate() {
+            seen += *count as u64;
+            if seen * 2 >= total {
+                split = pos.clamp(0, colors.len() - 2) + 1;
+                break;
+            }
+        }
+        let upper = colors.split_off(split);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+    boxes.iter().map(|colors| box_mean(colors)).collect()
+}
+
+fn channel_range<const N: usize>(colors: &[([u8; N], u32)]) -> (usize, u8) {
+    (0..N)
+        .map(|channel| {
+            let min = colors.iter().map(|(c, _)| c[channel]).min().unwrap_or(0);
+            let max = colors.iter().map(|(c, _)| c[channel]).max().unwrap_or(0);
+            (channel, max - min)
+        })
+        .max_by_key(|&(_, range)| range)
+        .unwrap_or((0, 0))
+}
+
+fn box_mean<const N: usize>(colors: &[([u8; N], u32)]) -> [u8; N] {
+    let mut sum = [0u64; N];
+    let mut total = 0u64;
+    for (color, count) in colors {
+        for channel in 0..N {
+            sum[channel] += color[channel] as u64 * *count as u64;
+        }
+        total += *count as u64;
+    }
+    sum.map(|s| ((s + total / 2) / total.max(1)) as u8)
+}
+
+/// Maps every pixel to the index of the nearest palette color.
+pub(crate) fn palette_indices<const N: usize>(pixels: &[[u8; N]], palette: &[[u8; N]]) -> Vec<u8> {
+    let mut cache: HashMap<[u8; N], u8> = HashMap::new();
+    pixels
+        .iter()
+        .map(|px| {
+            *cache.entry(*px).or_insert_with(|| {
+                palette
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, color)| {
+                        (0..N)
+                            .map(|c| (px[c] as i32 - color[c] as i32).pow(2))
+                            .sum::<i32>()
+                    })
+                    .map_or(0, |(i, _)| i as u8)
+            })
+        })
+        .collect()
+}
+
+/// Packs 1, 2 or 4 bit samples into bytes, each row starts on a byte boundary.
+pub(crate) fn pack_rows(samples: &[u8], width: usize, bits: u8) -> Vec<u8> {
+    if bits == 8 {
+        return samples.to_vec();
+    }
+    let per_byte = 8 / bits as usize;
+    let row_bytes = width.div_ceil(per_byte);
+    let mut out = Vec::with_capacity(row_bytes * samples.len() / width.max(1));
+    for row in samples.chunks(width.max(1)) {
+        for chunk in row.chunks(per_byte) {
+            let mut byte = 0u8;
+            for (i, &sample) in chunk.iter().enumerate() {
+                byte |= sample << (8 - bits as usize * (i + 1));
+            }
+            out.push(byte);
+        }
+    }
+    out
+}
@@ -0,0 +1,1132 @@
+use std::borrow::Cow;
+use std::error::Error;
+use std::io::Read;
+use std::ops::Range;
+
+use flate2::read::ZlibDecoder;
+use image::DynamicImage;
+
+use crate::utils::core::convert::buffer;
+use crate::utils::image::icc::PSD_ICC_PROFILE;
+use crate::utils::image::metadata::tiff_tags;
+use crate::utils::image::native::{NativeImage, NativeSpace};
+use crate::utils::image::region::Region;
+
+// Minimal reader for the merged (composite) image of PSD and PSB files.
+// Unlike zune-psd it keeps the source bit depth (8, 16 and 32 bit float),
+// supports RLE at every depth, extra alpha channels and every color mode.
+
+pub(crate) const PSD_MAGIC: [u8; 4] = [56, 66, 80, 83];
+
+pub(crate) const PSD_BITMAP: u16 = 0;
+pub(crate) const PSD_GRAYSCALE: u16 = 1;
+pub(crate) const PSD_INDEXED: u16 = 2;
+pub(crate) const PSD_RGB: u16 = 3;
+pub(crate) const PSD_CMYK: u16 = 4;
+pub(crate) const PSD_MULTICHANNEL: u16 = 7;
+pub(crate) const PSD_DUOTONE: u16 = 8;
+pub(crate) const PSD_LAB: u16 = 9;
+
+/// Largest width and height of psd and psb files.
+const PSD_MAX_SIZE: usize = 30000;
+const PSB_MAX_SIZE: usize = 300000;
+
+/// Image resource with the palette index that is transparent in indexed files.
+const PSD_TRANSPARENT_INDEX: u16 = 1047;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct PsdHeader {
+    pub psb: bool,
+    pub channels: usize,
+    pub height: usize,
+    pub width: usize,
+    pub depth: u16,
+    pub color_mode: u16,
+}
+
+impl PsdHeader {
+    pub fn parse(bytes: &[u8]) -> Result<PsdHeader, Box<dyn Error>> {
+        if bytes.len() < 26 || bytes[..4] != PSD_MAGIC {
+            return Err("not a psd file".into());
+        }
+        let version = be_u16(&bytes[4..6]);
+        if version != 1 && version != 2 {
+            return Err(format!("Unsupported psd version: {}", version).into());
+        }
+        let header = PsdHeader {
+            psb: version == 2,
+            channels: be_u16(&bytes[12..14]) as usize,
+            height: be_u32(&bytes[14..18]) as usize,
+            width: be_u32(&bytes[18..22]) as usize,
+            depth: be_u16(&bytes[22..24]),
+            color_mode: be_u16(&bytes[24..26]),
+        };
+        if header.channels == 0 || header.height == 0 || header.width == 0 {
+            return Err("psd has zero dimensions".into());
+        }
+        if header.width > header.max_size() || header.height > header.max_size() {
+            return Err(format!(
+                "psd dimensions {}x{} are over the {} limit",
+                header.width,
+                header.height,
+                header.max_size()
+            )
+            .into());
+        }
+        Ok(header)
+    }
+
+    /// Largest width and height of the image and of its layers.
+    pub fn max_size(&self) -> usize {
+        if self.psb {
+            PSB_MAX_SIZE
+        } else {
+            PSD_MAX_SIZE
+        }
+    }
+
+    pub fn color_mode_name(&self) -> &'static str {
+        match self.color_mode {
+            PSD_BITMAP => "bitmap",
+            PSD_GRAYSCALE => "gray",
+            PSD_INDEXED => "indexed",
+            PSD_RGB => "rgb",
+            PSD_CMYK => "cmyk",
+            PSD_MULTICHANNEL => "multichannel",
+            PSD_DUOTONE => "duotone",
+            PSD_LAB => "lab",
+            _ => "unknown",
+        }
+    }
+
+    /// Number of channels that carry color for the file color mode (indexed files
+    /// store one channel of palette indices, multichannel files have no alpha).
+    pub fn color_channels(&self) -> usize {
+        match self.color_mode {
+            PSD_RGB | PSD_LAB => 3,
+            PSD_CMYK => 4,
+            PSD_MULTICHANNEL => self.channels,
+            _ => 1,
+        }
+    }
+
+    /// Color space kept as native samples, `DynamicImage` has no room for it.
+    pub fn native_space(&self) -> Option<NativeSpace> {
+        match self.color_mode {
+            PSD_CMYK => Some(NativeSpace::Cmyk),
+            PSD_LAB => Some(NativeSpace::Lab),
+            PSD_MULTICHANNEL => Some(NativeSpace::Multichannel),
+            _ => None,
+        }
+    }
+
+    /// Bytes of one unpacked row of one channel.
+    pub fn row_bytes(&self) -> usize {
+        match self.depth {
+            1 => self.width.div_ceil(8),
+            depth => self.width * (depth as usize / 8),
+        }
+    }
+}
+
+pub(crate) struct PsdFile<'a> {
+    pub header: PsdHeader,
+    /// Palette of indexed files (256 reds, 256 greens, 256 blues), duotone specs.
+    pub color_data: &'a [u8],
+    pub resources: &'a [u8],
+    pub layers: &'a [u8],
+    data: &'a [u8],
+    compression: u16,
+    image_data: usize,
+    /// Packed rows of every channel of rle compressed files.
+    rle_rows: Cow<'a, [Range<usize>]>,
+}
+
+impl<'a> PsdFile<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<PsdFile<'a>, Box<dyn Error>> {
+        PsdFile::parse_with_rows(data, None)
+    }
+
+    /// Parses the file with the row table of the merged image from an earlier parse of
+    /// the same file (`into_rle_rows`) instead of reading every row count again.
+    pub fn parse_with_rows(
+        data: &'a [u8],
+        rle_rows: Option<&'a [Range<usize>]>,
+    ) -> Result<PsdFile<'a>, Box<dyn Error>> {
+        let header = PsdHeader::parse(data)?;
+        let mut pos = 26;
+        let color_data = section(data, &mut pos, false)?;
+        let resources = section(data, &mut pos, false)?;
+        let layers = section(data, &mut pos, header.psb)?;
+        let compression = be_u16(take(data, pos, 2)?);
+        pos += 2;
+
+        let mut table = Vec::new();
+        match compression {
+            0 => {
+                // the raw planes must all be in the file before anything is allocated
+                let end = header
+                    .row_bytes()
+                    .checked_mul(header.height)
+                    .and_then(|plane| plane.checked_mul(header.channels))
+                    .and_then(|planes| planes.checked_add(pos));
+                if end.is_none_or(|end| end > data.len()) {
+                    return Err("psd image data is truncated".into());
+                }
+            }
+            1 if rle_rows.is_some() => {}
+            1 => {
+                // byte counts of every row of every channel precede the packed rows
+                let rows = header.channels * header.height;
+                let count_size = if header.psb { 4 } else { 2 };
+                let counts = take(data, pos, rows * count_size)?;
+                // a packbits run unpacks at most 128 bytes from 2, shorter rows can not
+                // fill the image that is allocated from the header
+                let min_len = header.row_bytes().div_ceil(128) * 2;
+                let mut start = pos + rows * count_size;
+                table.reserve(rows);
+                for count in counts.chunks_exact(count_size) {
+                    let len = if header.psb {
+                        be_u32(count) as usize
+                    } else {
+                        be_u16(count) as usize
+                    };
+                    if len < min_len || len > data.len() - start {
+                        return Err("psd image data is truncated".into());
+                    }
+                    table.push(start..start + len);
+                    start += len;
+                }
+            }
+            _ => {
+                return Err(format!("Unsupported psd compression: {}", compression).into());
+            }
+        }
+        Ok(PsdFile {
+            header,
+            color_data,
+            resources,
+            layers,
+            data,
+            compression,
+            image_data: pos,
+            rle_rows: match rle_rows {
+                Some(rows) => Cow::Borrowed(rows),
+                None => Cow::Owned(table),
+            },
+        })
+    }
+
+    /// Row table of the merged image to parse the file again with `parse_with_rows`.
+    pub fn into_rle_rows(self) -> Vec<Range<usize>> {
+        self.rle_rows.into_owned()
+    }
+
+    /// Finds an image resource block by id (1005 resolution, 1039 icc profile, 1058 exif...).
+    pub fn resource(&self, id: u16) -> Option<&'a [u8]> {
+        let mut reader = ByteReader::new(self.resources);
+        while reader.remaining() >= 12 {
+            if reader.bytes(4).ok()? != b"8BIM" {
+                return None;
+            }
+            let block_id = reader.u16().ok()?;
+            // pascal name padded to an even size
+            let name_len = reader.u8().ok()? as usize;
+            reader.bytes(name_len + (name_len + 1) % 2).ok()?;
+            let len = reader.u32().ok()? as usize;
+            let data = reader.bytes(len).ok()?;
+            reader.bytes(len % 2).ok()?;
+            if block_id == id {
+                return Some(data);
+            }
+        }
+        None
+    }
+
+    /// Whether the first extra channel is the transparency of the merged image,
+    /// flagged by a negative layer count. Other extra channels are saved selections
+    /// and spot colors and are not decoded.
+    pub fn has_alpha(&self) -> bool {
+        self.header.channels > self.header.color_channels()
+            && self
+                .layer_info()
+                .is_ok_and(|info| info.len() >= 2 && (be_u16(info) as i16) < 0)
+    }
+
+    /// Color channels plus the transparency when there is one.
+    fn out_channels(&self) -> usize {
+        self.header.color_channels() + self.has_alpha() as usize
+    }
+
+    /// Exif orientation stored in the exif resource.
+    pub fn orientation(&self) -> Option<u8> {
+        tiff_tags(self.resource(1058)?)?.orientation
+    }
+
+    /// Unpacks one row of one channel of the merged image as big-endian bytes.
+    pub fn read_row(&self, channel: usize, y: usize, out: &mut [u8]) -> Result<(), Box<dyn Error>> {
+        let row_bytes = self.header.row_bytes();
+        let row = channel * self.header.height + y;
+        if self.compression == 0 {
+            out.copy_from_slice(take(
+                self.data,
+                self.image_data + row * row_bytes,
+                row_bytes,
+            )?);
+            Ok(())
+        } else {
+            let range = self.rle_rows[row].clone();
+            let packed = take(self.data, range.start, range.len())?;
+            unpack_bits(packed, &mut out[..row_bytes])
+        }
+    }
+
+    /// Row reader over the columns of `region` for `planar_image`, rows are counted
+    /// from the top of the region.
+    fn region_rows(
+        &self,
+        region: Region,
+    ) -> impl FnMut(usize, usize, &mut [u8]) -> Result<(), Box<dyn Error>> + '_ {
+        let sample_bytes = self.header.depth as usize / 8;
+        let mut full = vec![0u8; self.header.row_bytes()];
+        move |channel, y, out| {
+            self.read_row(channel, region.y + y, &mut full)?;
+            out.copy_from_slice(&full[region.x * sample_bytes..][..out.len()]);
+            Ok(())
+        }
+    }
+
+    /// Decodes the merged image keeping its bit depth.
+    ///
+    /// Bitmap files are expanded to gray, indexed files to rgb through the palette,
+    /// duotone files are read as their gray channel and cmyk, lab and multichannel
+    /// files are converted to rgb (cmyk through the embedded icc profile).
+    ///
+    /// # Returns
+    /// The image and the native channel count of the file (gray images
+    /// stored in 32 bit are widened to rgb, as `DynamicImage` has no 32 bit gray).
+    pub fn decode(&self) -> Result<(DynamicImage, u8), Box<dyn Error>> {
+        self.decode_region(Region::full(self.header.width, self.header.height))
+    }
+
+    /// Decodes the `region` rectangle of the merged image like `decode`, only the
+    /// rows of the region are unpacked.
+    pub fn decode_region(&self, region: Region) -> Result<(DynamicImage, u8), Box<dyn Error>> {
+        let header = self.header;
+        region.check(header.width, header.height)?;
+        let img = match header.color_mode {
+            PSD_BITMAP => Some(self.decode_bitmap(region)?),
+            PSD_INDEXED => Some(self.decode_indexed(region)?),
+            PSD_GRAYSCALE | PSD_DUOTONE | PSD_RGB => None,
+            _ => match self.decode_native_region(region)? {
+                Some(native) => Some(native.to_dynamic(self.resource(PSD_ICC_PROFILE))?),
+                None => {
+                    return Err(format!("Unsupported psd color mode: {}", header.color_mode).into())
+                }
+            },
+        };
+        if let Some(img) = img {
+            let channels = img.color().channel_count();
+            return Ok((img, channels));
+        }
+        let channels = self.out_channels();
+        let img = planar_image(
+            header.depth,
+            region.width,
+            region.height,
+            channels,
+            self.has_alpha(),
+            self.region_rows(region),
+        )?;
+        Ok((img, channels as u8))
+    }
+
+    /// Decodes the merged image of cmyk, lab and multichannel files as samples
+    /// in 0-1 (cmyk as ink amounts), None for the other color modes.
+    pub fn decode_native(&self) -> Result<Option<NativeImage>, Box<dyn Error>> {
+        self.decode_native_region(Region::full(self.header.width, self.header.height))
+    }
+
+    /// Decodes the `region` rectangle like `decode_native`.
+    pub fn decode_native_region(
+        &self,
+        region: Region,
+    ) -> Result<Option<NativeImage>, Box<dyn Error>> {
+        let header = self.header;
+        let Some(space) = header.native_space() else {
+            return Ok(None);
+        };
+        region.check(header.width, header.height)?;
+        let samples = planar_samples(
+            header.depth,
+            region.width,
+            region.height,
+            self.out_channels(),
+            self.region_rows(region),
+        )?;
+        let alpha = self.has_alpha();
+        Ok(Some(native_image(
+            space,
+            samples,
+            &header,
+            (region.width, region.height),
+            alpha,
+            alpha,
+        )))
+    }
+
+    /// Expands a 1 bit file to gray, set bits are black.
+    fn decode_bitmap(&self, region: Region) -> Result<DynamicImage, Box<dyn Error>> {
+        let header = self.header;
+        let mut row = vec![0u8; header.row_bytes()];
+        let mut px = Vec::with_capacity(region.width * region.height);
+        for y in region.rows() {
+            self.read_row(0, y, &mut row)?;
+            px.extend(region.columns().map(|x| {
+                if row[x / 8] & (0x80 >> (x % 8)) != 0 {
+                    0
+                } else {
+                    255
+                }
+            }));
+        }
+        let (w, h) = (region.width as u32, region.height as u32);
+        Ok(DynamicImage::ImageLuma8(buffer(w, h, px)?))
+    }
+
+    /// Maps the palette indices of an indexed file to rgb, rgba when the palette
+    /// has a transparent entry.
+    fn decode_indexed(&self, region: Region) -> Result<DynamicImage, Box<dyn Error>> {
+        let header = self.header;
+        if header.depth != 8 {
+            return Err(format!("Unsupported bits: {}", header.depth).into());
+        }
+        let palette = self
+            .color_data
+            .get(..768)
+            .ok_or("psd indexed palette is missing")?;
+        let transparent = self
+            .resource(PSD_TRANSPARENT_INDEX)
+            .filter(|data| data.len() >= 2)
+            .map(|data| be_u16(data) as usize);
+        let channels = if transparent.is_some() { 4 } else { 3 };
+        let mut row = vec![0u8; header.row_bytes()];
+        let mut px = Vec::with_capacity(region.width * region.height * channels);
+        for y in region.rows() {
+            self.read_row(0, y, &mut row)?;
+            for &index in &row[region.columns()] {
+                let i = index as usize;
+                px.extend_from_slice(&[palette[i], palette[256 + i], palette[512 + i]]);
+                if let Some(transparent) = transparent {
+                    px.push(if i == transparent { 0 } else { 255 });
+                }
+            }
+        }
+        let (w, h) = (region.width as u32, region.height as u32);
+        Ok(match transparent {
+            Some(_) => DynamicImage::ImageRgba8(buffer(w, h, px)?),
+            None => DynamicImage::ImageRgb8(buffer(w, h, px)?),
+        })
+    }
+
+    /// Decodes every layer of the file, bottom to top as they are stored.
+    ///
+    /// Layers of cmyk and lab files are converted to rgba like the merged image.
+    pub fn decode_layers(&self) -> Result<Vec<PsdLayer>, Box<dyn Error>> {
+        let info = self.layer_info()?;
+        if info.is_empty() {
+            return Ok(Vec::new());
+        }
+        layer_info(info, &self.header, self.resource(PSD_ICC_PROFILE))
+    }
+
+    /// Layer info of the layer section, empty when the file has no layers.
+    fn layer_info(&self) -> Result<&'a [u8], Box<dyn Error>> {
+        let mut reader = ByteReader::new(self.layers);
+        if reader.is_empty() {
+            return Ok(&[]);
+        }
+        let info = reader.bytes_len(self.header.psb)?;
+        if !info.is_empty() {
+            return Ok(info);
+        }
+        // 16 and 32 bit documents keep their layers in a tagged block after the global mask
+        reader.bytes_len(false)?;
+        Ok(tagged_blocks(&mut reader, self.header.psb)?
+            .into_iter()
+            .find(|(key, _)| key == b"Lr16" || key == b"Lr32" || key == b"Layr")
+            .map(|(_, data)| data)
+            .unwrap_or_default())
+    }
+}
+
+/// What a layer record holds, read from its section divider and text blocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PsdLayerKind {
+    Pixel,
+    Text,
+    /// Opens a layer group, stored after its content.
+    Group,
+    /// The hidden divider closing a group.
+    GroupEnd,
+}
+
+impl PsdLayerKind {
+    pub fn from_name(name: &str) -> Option<PsdLayerKind> {
+        match name {
+            "pixel" => Some(PsdLayerKind::Pixel),
+            "text" => Some(PsdLayerKind::Text),
+            "group" => Some(PsdLayerKind::Group),
+            "group_end" => Some(PsdLayerKind::GroupEnd),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PsdLayerKind::Pixel => "pixel",
+            PsdLayerKind::Text => "text",
+            PsdLayerKind::Group => "group",
+            PsdLayerKind::GroupEnd => "group_end",
+        }
+    }
+
+    /// Groups and their dividers carry no pixels.
+    pub fn has_pixels(&self) -> bool {
+        !matches!(self, PsdLayerKind::Group | PsdLayerKind::GroupEnd)
+    }
+}
+
+pub(crate) struct PsdLayer {
+    pub name: String,
+    pub kind: PsdLayerKind,
+    pub visible: bool,
+    pub opacity: u8,
+    pub blend_mode: String,
+    pub top: i32,
+    pub left: i32,
+    /// Layer pixels with transparency and the native channel count (color + alpha).
+    pub image: DynamicImage,
+    pub channels: u8,
+}
+
+struct LayerRecord {
+    name: String,
+    kind: PsdLayerKind,
+    visible: bool,
+    opacity: u8,
+    blend_mode: String,
+    top: i32,
+    left: i32,
+    bottom: i32,
+    right: i32,
+    channels: Vec<(i16, usize)>,
+}
+
+fn layer_info(
+    info: &[u8],
+    header: &PsdHeader,
+    icc: Option<&[u8]>,
+) -> Result<Vec<PsdLayer>, Box<dyn Error>> {
+    let mut reader = ByteReader::new(info);
+    // a negative count means the first alpha channel holds the merged transparency
+    let count = (reader.u16()? as i16).unsigned_abs() as usize;
+    let mut records = Vec::with_capacity(count);
+    for _ in 0..count {
+        records.push(layer_record(&mut reader, header.psb)?);
+    }
+
+    let color_channels = header.color_channels();
+    let mut layers = Vec::with_capacity(count);
+    for record in records {
+        let size = |low: i32, high: i32| {
+            let size = high
+                .checked_sub(low)
+                .ok_or("psd layer bounds overflow")?
+                .max(0) as usize;
+            if size > header.max_size() {
+                return Err(format!(
+                    "psd layer size {} is over the {} limit",
+                    size,
+                    header.max_size()
+                ));
+            }
+            Ok(size)
+        };
+        let width = size(record.left, record.right)?;
+        let height = size(record.top, record.bottom)?;
+        let mut planes: Vec<Option<Vec<u8>>> = vec![None; color_channels + 1];
+        for (id, len) in &record.channels {
+            let data = reader.bytes(*len)?;
+            // -2 and -3 are user masks with their own bounds, they are skipped
+            let index = match *id {
+                -1 => color_channels,
+                id if id >= 0 && (id as usize) < color_channels => id as usize,
+                _ => continue,
+            };
+            planes[index] = Some(layer_channel(data, header, width, height)?);
+        }
+        // the image is allocated from the bounds, some channel must back them
+        if width * height > 0 && planes.iter().all(Option::is_none) {
+            return Err("psd layer has no channel data".into());
+        }
+        let row_bytes = width * (header.depth as usize / 8);
+        let opaque = opaque_row(header.depth, width);
+        let read_row = |c: usize, y: usize, row: &mut [u8]| {
+            match &planes[c] {
+                Some(plane) => row.copy_from_slice(&plane[y * row_bytes..(y + 1) * row_bytes]),
+                None if c == color_channels => row.copy_from_slice(&opaque),
+                None => row.fill(0),
+            }
+            Ok(())
+        };
+        let (image, channels) = match header.native_space() {
+            Some(space) => {
+                let samples =
+                    planar_samples(header.depth, width, height, color_channels + 1, read_row)?;
+                let native = native_image(space, samples, header, (width, height), true, false);
+                let image = native.to_dynamic(icc)?;
+                let channels = image.color().channel_count();
+                (image, channels)
+            }
+            None => (
+                planar_image(
+                    header.depth,
+                    width,
+                    height,
+                    color_channels + 1,
+                    false,
+                    read_row,
+                )?,
+                color_channels as u8 + 1,
+            ),
+        };
+        layers.push(PsdLayer {
+            name: record.name,
+            kind: record.kind,
+            visible: record.visible,
+            opacity: record.opacity,
+            blend_mode: record.blend_mode,
+            top: record.top,
+            left: record.left,
+            image,
+            channels,
+        });
+    }
+    Ok(layers)
+}
+
+fn layer_record(reader: &mut ByteReader, psb: bool) -> Result<LayerRecord, Box<dyn Error>> {
+    let top = reader.u32()? as i32;
+    let left = reader.u32()? as i32;
+    let bottom = reader.u32()? as i32;
+    let right = reader.u32()? as i32;
+    let channel_count = reader.u16()? as usize;
+    let mut channels = Vec::with_capacity(channel_count);
+    for _ in 0..channel_count {
+        let id = reader.u16()? as i16;
+        let len = reader.len(psb)?;
+        channels.push((id, len));
+    }
+    if reader.bytes(4)? != b"8BIM" {
+        return Err("psd layer record has a wrong blend mode signature".into());
+    }
+    let blend_mode = String::from_utf8_lossy(reader.bytes(4)?)
+        .trim_end()
+        .to_string();
+    let opacity = reader.u8()?;
+    let _clipping = reader.u8()?;
+    let flags = reader.u8()?;
+    let _filler = reader.u8()?;
+
+    let mut extra = ByteReader::new(reader.bytes_len(false)?);
+    // layer mask and blending ranges
+    extra.bytes_len(false)?;
+    extra.bytes_len(false)?;
+    let name_len = extra.u8()? as usize;
+    let mut name = String::from_utf8_lossy(extra.bytes(name_len)?).to_string();
+    // pascal string padded to a multiple of 4 bytes
+    extra.bytes((4 - (name_len + 1) % 4) % 4)?;
+
+    let mut kind = PsdLayerKind::Pixel;
+    for (key, data) in tagged_blocks(&mut extra, psb)? {
+        match &key {
+            b"luni" if data.len() >= 4 => {
+                let chars = (be_u32(data) as usize).min((data.len() - 4) / 2);
+                let utf16: Vec<u16> = data[4..4 + chars * 2].chunks_exact(2).map(be_u16).collect();
+                name = String::from_utf16_lossy(&utf16);
+            }
+            b"lsct" if data.len() >= 4 => match be_u32(data) {
+                1 | 2 => kind = PsdLayerKind::Group,
+                3 => kind = PsdLayerKind::GroupEnd,
+                _ => {}
+            },
+            b"TySh" => kind = PsdLayerKind::Text,
+            _ => {}
+        }
+    }
+    Ok(LayerRecord {
+        name,
+        kind,
+        visible: flags & 2 == 0,
+        opacity,
+        blend_mode,
+        top,
+        left,
+        bottom,
+        right,
+        channels,
+    })
+}
+
+/// Unpacks the pixel data of one layer channel as big-endian bytes.
+fn layer_channel(
+    data: &[u8],
+    header: &PsdHeader,
+    width: usize,
+    height: usize,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let row_bytes = width * (header.depth as usize / 8);
+    let len = row_bytes * height;
+    if len == 0 {
+        return Ok(Vec::new());
+    }
+    if data.len() < 2 {
+        return Err("psd layer channel is truncated".into());
+    }
+    let body = &data[2..];
+    // the channel data must be able to fill the plane before it is allocated
+    let min_len = match be_u16(data) {
+        0 => len,
+        // row byte counts and packbits runs of at most 128 bytes
+        1 => height * (if header.psb { 4 } else { 2 } + row_bytes.div_ceil(128) * 2),
+        // deflate packs at most 1032 bytes into one
+        _ => len.div_ceil(1032),
+    };
+    if body.len() < min_len {
+        return Err("psd layer channel is truncated".into());
+    }
+    let mut out = vec![0u8; len];
+    match be_u16(data) {
+        0 => out.copy_from_slice(&body[..len]),
+        1 => {
+            let count_size = if header.psb { 4 } else { 2 };
+            let mut start = height * count_size;
+            for y in 0..height {
+                let count = take(body, y * count_size, count_size)?;
+                let len = if header.psb {
+                    be_u32(count) as usize
+                } else {
+                    be_u16(count) as usize
+                };
+                unpack_bits(
+                    take(body, start, len)?,
+                    &mut out[y * row_bytes..(y + 1) * row_bytes],
+                )?;
+                start += len;
+            }
+        }
+        compression @ (2 | 3) => {
+            ZlibDecoder::new(body).read_exact(&mut out)?;
+            if compression == 3 {
+                unpredict(&mut out, row_bytes, header.depth);
+            }
+        }
+        compression => {
+            return Err(format!("Unsupported psd layer compression: {}", compression).into());
+        }
+    }
+    Ok(out)
+}
+
+fn unpredict(px: &mut [u8], row_bytes: usize, depth: u16) {
+    // zip with prediction stores every row as deltas of the previous sample
+    for row in px.chunks_exact_mut(row_bytes) {
+        match depth {
+            16 => {
+                for x in 1..row_bytes / 2 {
+                    let value = be_u16(&row[x * 2 - 2..]).wrapping_add(be_u16(&row[x * 2..]));
+                    row[x * 2..x * 2 + 2].copy_from_slice(&value.to_be_bytes());
+                }
+            }
+            32 => {
+                // bytes are delta coded over the whole row and split into byte planes
+                for x in 1..row_bytes {
+                    row[x] = row[x].wrapping_add(row[x - 1]);
+                }
+                let width = row_bytes / 4;
+                let planes = row.to_vec();
+                for x in 0..width {
+                    for b in 0..4 {
+                        row[x * 4 + b] = planes[b * width + x];
+                    }
+                }
+            }
+            _ => {
+                for x in 1..row_bytes {
+                    row[x] = row[x].wrapping_add(row[x - 1]);
+                }
+            }
+        }
+    }
+}
+
+fn opaque_row(depth: u16, width: usize) -> Vec<u8> {
+    match depth {
+        32 => 1.0f32.to_be_bytes().repeat(width),
+        depth => vec![255; width * (depth as usize / 8)],
+    }
+}
+
+/// Key and data of every tagged block (additional layer information).
+type TaggedBlocks<'a> = Vec<([u8; 4], &'a [u8])>;
+
+fn tagged_blocks<'a>(
+    reader: &mut ByteReader<'a>,
+    psb: bool,
+) -> Result<TaggedBlocks<'a>, Box<dyn Error>> {
+    let mut blocks = Vec::new();
+    while reader.remaining() >= 12 {
+        let signature = reader.bytes(4)?;
+        if signature != b"8BIM" && signature != b"8B64" {
+            break;
+        }
+        let key: [u8; 4] = reader.bytes(4)?.try_into()?;
+        // psb widens the length of the blocks that can hold pixel data
+        let long = psb
+            && matches!(
+                &key,
+                b"LMsk"
+                    | b"Lr16"
+                    | b"Lr32"
+                    | b"Layr"
+                    | b"Mt16"
+                    | b"Mt32"
+                    | b"Mtrn"
+                    | b"Alph"
+                    | b"FMsk"
+                    | b"lnk2"
+                    | b"FEid"
+                    | b"FXid"
+                    | b"PxSD"
+            );
+        let data = reader.bytes_len(long)?;
+        blocks.push((key, data));
+    }
+    Ok(blocks)
+}
+
+struct ByteReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(data: &'a [u8]) -> ByteReader<'a> {
+        ByteReader { data, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        let bytes = take(self.data, self.pos, len)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Box<dyn Error>> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Box<dyn Error>> {
+        Ok(be_u16(self.bytes(2)?))
+    }
+
+    fn u32(&mut self) -> Result<u32, Box<dyn Error>> {
+        Ok(be_u32(self.bytes(4)?))
+    }
+
+    /// Reads a length that is 8 bytes wide for the psb fields that grew.
+    fn len(&mut self, long: bool) -> Result<usize, Box<dyn Error>> {
+        if long {
+            Ok(u64::from_be_bytes(self.bytes(8)?.try_into()?) as usize)
+        } else {
+            Ok(self.u32()? as usize)
+        }
+    }
+
+    fn bytes_len(&mut self, long: bool) -> Result<&'a [u8], Box<dyn Error>> {
+        let len = self.len(long)?;
+        self.bytes(len)
+    }
+}
+
+/// Builds an image from planar rows of big-endian samples.
+///
+/// `matted` marks a merged image whose colors were blended over white by photoshop.
+fn planar_image(
+    depth: u16,
+    width: usize,
+    height: usize,
+    channels: usize,
+    matted: bool,
+    mut read_row: impl FnMut(usize, usize, &mut [u8]) -> Result<(), Box<dyn Error>>,
+) -> Result<DynamicImage, Box<dyn Error>> {
+    let (w, h) = (width as u32, height as u32);
+    let row_bytes = width * (depth as usize / 8);
+    let img = match depth {
+        8 => {
+            let mut px = interleave(
+                width,
+                height,
+                channels,
+                row_bytes,
+                &mut read_row,
+                |row, x| row[x],
+            )?;
+            if matted {
+                unmatte(&mut px, channels, |v| v as f32, |v| v.round() as u8, 255.0);
+            }
+            match channels {
+                1 => DynamicImage::ImageLuma8(buffer(w, h, px)?),
+                2 => DynamicImage::ImageLumaA8(buffer(w, h, px)?),
+                3 => DynamicImage::ImageRgb8(buffer(w, h, px)?),
+                _ => DynamicImage::ImageRgba8(buffer(w, h, px)?),
+            }
+        }
+        16 => {
+            let mut px = interleave(
+                width,
+                height,
+                channels,
+                row_bytes,
+                &mut read_row,
+                |row, x| be_u16(&row[x * 2..x * 2 + 2]),
+            )?;
+            if matted {
+                unmatte(
+                    &mut px,
+                    channels,
+                    |v| v as f32,
+                    |v| v.round() as u16,
+                    65535.0,
+                );
+            }
+            match channels {
+                1 => DynamicImage::ImageLuma16(buffer(w, h, px)?),
+                2 => DynamicImage::ImageLumaA16(buffer(w, h, px)?),
+                3 => DynamicImage::ImageRgb16(buffer(w, h, px)?),
+                _ => DynamicImage::ImageRgba16(buffer(w, h, px)?),
+            }
+        }
+        32 => {
+            let mut px = interleave(
+                width,
+                height,
+                channels,
+                row_bytes,
+                &mut read_row,
+                |row, x| {
+                    f32::from_be_bytes([row[x * 4], row[x * 4 + 1], row[x * 4 + 2], row[x * 4 + 3]])
+                },
+            )?;
+            if matted {
+                unmatte(&mut px, channels, |v| v, |v| v, 1.0);
+            }
+            match channels {
+                1 => DynamicImage::ImageRgb32F(buffer(w, h, gray_to_rgb(&px, 1))?),
+                2 => DynamicImage::ImageRgba32F(buffer(w, h, gray_to_rgb(&px, 2))?),
+                3 => DynamicImage::ImageRgb32F(buffer(w, h, px)?),
+                _ => DynamicImage::ImageRgba32F(buffer(w, h, px)?),
+            }
+        }
+        depth => return Err(format!("Unsupported bits: {}", depth).into()),
+    };
+    Ok(img)
+}
+
+/// Reads planar rows of big-endian samples as interleaved floats in 0-1.
+fn planar_samples(
+    depth: u16,
+    width: usize,
+    height: usize,
+    channels: usize,
+    mut read_row: impl FnMut(usize, usize, &mut [u8]) -> Result<(), Box<dyn Error>>,
+) -> Result<Vec<f32>, Box<dyn Error>> {
+    let row_bytes = width * (depth as usize / 8);
+    match depth {
+        8 => interleave(
+            width,
+            height,
+            channels,
+            row_bytes,
+            &mut read_row,
+            |row, x| row[x] as f32 / 255.0,
+        ),
+        16 => interleave(
+            width,
+            height,
+            channels,
+            row_bytes,
+            &mut read_row,
+            |row, x| be_u16(&row[x * 2..x * 2 + 2]) as f32 / 65535.0,
+        ),
+        32 => interleave(
+            width,
+            height,
+            channels,
+            row_bytes,
+            &mut read_row,
+            |row, x| {
+                f32::from_be_bytes([row[x * 4], row[x * 4 + 1], row[x * 4 + 2], row[x * 4 + 3]])
+            },
+        ),
+        depth => Err(format!("Unsupported bits: {}", depth).into()),
+    }
+}
+
+/// Wraps planar samples of a cmyk, lab or multichannel image, cmyk is stored
+/// inverted (1 = no ink) and turned into ink amounts.
+fn native_image(
+    space: NativeSpace,
+    mut samples: Vec<f32>,
+    header: &PsdHeader,
+    (width, height): (usize, usize),
+    alpha: bool,
+    matted: bool,
+) -> NativeImage {
+    let color_channels = header.color_channels();
+    let channels = color_channels + alpha as usize;
+    // white is the maximum of every channel in cmyk, not in lab
+    if matted && space != NativeSpace::Lab {
+        unmatte(&mut samples, channels, |v| v, |v| v, 1.0);
+    }
+    if space == NativeSpace::Cmyk {
+        for pixel in samples.chunks_exact_mut(channels) {
+            for value in &mut pixel[..color_channels] {
+                *value = 1.0 - *value;
+            }
+        }
+    }
+    NativeImage {
+        width,
+        height,
+        space,
+        color_channels,
+        alpha,
+        depth: header.depth,
+        samples,
+    }
+}
+
+fn interleave<T: Copy + Default>(
+    width: usize,
+    height: usize,
+    channels: usize,
+    row_bytes: usize,
+    read_row: &mut impl FnMut(usize, usize, &mut [u8]) -> Result<(), Box<dyn Error>>,
+    sample: impl Fn(&[u8], usize) -> T,
+) -> Result<Vec<T>, Box<dyn Error>> {
+    let mut px = vec![T::default(); width * height * channels];
+    let mut row = vec![0u8; row_bytes];
+    for c in 0..channels {
+        for y in 0..height {
+            read_row(c, y, &mut row)?;
+            let out = &mut px[y * width * channels..(y + 1) * width * channels];
+            for x in 0..width {
+                out[x * channels + c] = sample(&row, x);
+            }
+        }
+    }
+    Ok(px)
+}
+
+pub(crate) fn be_u16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+pub(crate) fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn take(data: &[u8], pos: usize, len: usize) -> Result<&[u8], Box<dyn Error>> {
+    data.get(pos..pos + len)
+        .ok_or_else(|| "psd file is truncated".into())
+}
+
+fn section<'a>(data: &'a [u8], pos: &mut usize, long: bool) -> Result<&'a [u8], Box<dyn Error>> {
+    let len = if long {
+        let len = take(data, *pos, 8)?;
+        *pos += 8;
+        u64::from_be_bytes(len.try_into()?) as usize
+    } else {
+        let len = be_u32(take(data, *pos, 4)?) as usize;
+        *pos += 4;
+        len
+    };
+    let body = take(data, *pos, len)?;
+    *pos += len;
+    Ok(body)
+}
+
+pub(crate) fn unpack_bits(packed: &[u8], out: &mut [u8]) -> Result<(), Box<dyn Error>> {
+    // PackBits: n < 128 copies n + 1 literal bytes, n > 128 repeats the next byte 257 - n times
+    let mut i = 0;
+    let mut o = 0;
+    while o < out.len() {
+        let n = *packed.get(i).ok_or("psd rle row is truncated")? as usize;
+        i += 1;
+        if n < 128 {
+            let len = n + 1;
+            let src = packed.get(i..i + len).ok_or("psd rle row is truncated")?;
+            out.get_mut(o..o + len)
+                .ok_or("psd rle row overflows")?
+                .copy_from_slice(src);
+            i += len;
+            o += len;
+        } else if n > 128 {
+            let len = 257 - n;
+            let value = *packed.get(i).ok_or("psd rle row is truncated")?;
+            out.get_mut(o..o + len)
+                .ok_or("psd rle row overflows")?
+                .fill(value);
+            i += 1;
+            o += len;
+        }
+    }
+    Ok(())
+}
+
+fn unmatte<T: Copy>(
+    px: &mut [T],
+    channels: usize,
+    to_f32: impl Fn(T) -> f32,
+    from_f32: impl Fn(f32) -> T,
+    max: f32,
+) {
+    // the merged image of a transparent document is blended over white, undo it
+    for pixel in px.chunks_exact_mut(channels) {
+        let a = to_f32(pixel[channels - 1]) / max;
+        if a > 0.0 && a < 1.0 {
+            for value in &mut pixel[..channels - 1] {
+                let v = (to_f32(*value) - max * (1.0 - a)) / a;
+                *value = from_f32(v.clamp(0.0, max));
+            }
+        }
+    }
+}
+
+pub(crate) fn gray_to_rgb(px: &[f32], channels: usize) -> Vec<f32> {
+    let mut rgb = Vec::with_capacity(px.len() / channels * (channels + 2));
+    for pixel in px.chunks_exact(channels) {
+        rgb.extend_from_slice(&[pixel[0], pixel[0], pixel[0]]);
+        rgb.extend_from_slice(&pixel[1..]);
+    }
+    rgb
+}
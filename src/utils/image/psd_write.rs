@@ -0,0 +1,432 @@
+use std::error::Error;
+
+use image::{DynamicImage, ImageBuffer};
+
+use crate::utils::image::psd::{PsdLayer, PsdLayerKind, PSD_GRAYSCALE, PSD_MAGIC, PSD_RGB};
+
+// Writer for layered PSD files: every layer with its name, opacity, visibility and
+// blend mode plus a flattened composite, 8 or 16 bit, gray or rgb. Channel data is
+// RLE compressed like photoshop does by default.
+
+/// Photoshop limit for psd (psb is not written).
+const PSD_MAX_SIZE: u32 = 30000;
+
+pub(crate) fn write_psd(
+    layers: &[PsdLayer],
+    size: Option<(u32, u32)>,
+    depth: u16,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    if depth != 8 && depth != 16 {
+        return Err("psd bit_depth must be 8 or 16".into());
+    }
+    let pixel_layers = || layers.iter().filter(|layer| layer.kind.has_pixels());
+    let (width, height) = size.unwrap_or_else(|| {
+        pixel_layers().fold((0, 0), |(w, h), layer| {
+            (
+                w.max((layer.left + layer.image.width() as i32).max(0) as u32),
+                h.max((layer.top + layer.image.height() as i32).max(0) as u32),
+            )
+        })
+    });
+    if width == 0 || height == 0 || width > PSD_MAX_SIZE || height > PSD_MAX_SIZE {
+        return Err(format!("psd size must be 1-{} pixels", PSD_MAX_SIZE).into());
+    }
+    let gray = pixel_layers().all(|layer| !layer.image.color().has_color());
+    let color_channels = if gray { 1 } else { 3 };
+
+    let mut out = Vec::new();
+    out.extend_from_slice(&PSD_MAGIC);
+    out.extend_from_slice(&1u16.to_be_bytes());
+    out.extend_from_slice(&[0; 6]);
+    out.extend_from_slice(&(color_channels as u16 + 1).to_be_bytes());
+    out.extend_from_slice(&height.to_be_bytes());
+    out.extend_from_slice(&width.to_be_bytes());
+    out.extend_from_slice(&depth.to_be_bytes());
+    let mode = if gray { PSD_GRAYSCALE } else { PSD_RGB };
+    out.extend_from_slice(&mode.to_be_bytes());
+    // color mode data and image resources
+    out.extend_from_slice(&0u32.to_be_bytes());
+    out.extend_from_slice(&0u32.to_be_bytes());
+
+    let info = layer_info(layers, gray, depth)?;
+    let mut section = Vec::new();
+    if depth == 8 {
+        put_block(&mut section, &info);
+        // global layer mask info
+        section.extend_from_slice(&0u32.to_be_bytes());
+    } else {
+        // photoshop keeps 16 bit layers in a tagged block after the global mask
+        section.extend_from_slice(&0u32.to_be_bytes());
+        section.extend_from_slice(&0u32.to_be_bytes());
+        section.extend_from_slice(b"8BIMLr16");
+        put_block(&mut section, &info);
+    }
+    put_block(&mut out, &section);
+
+    let composite = composite(layers, width, height, gray);
+    out.extend_from_slice(&1u16.to_be_bytes());
+    let planes = planes(&composite, depth, true);
+    let rows: Vec<Vec<u8>> = planes
+        .iter()
+        .flat_map(|plane| plane.chunks(plane.len() / height as usize))
+        .map(pack_bits)
+        .collect();
+    for row in &rows {
+        out.extend_from_slice(&(row.len() as u16).to_be_bytes());
+    }
+    for row in &rows {
+        out.extend_from_slice(row);
+    }
+    Ok(out)
+}
+
+/// Appends a u32 length prefixed block padded to an even size.
+fn put_block(out: &mut Vec<u8>, data: &[u8]) {
+    let padded = data.len() + data.len() % 2;
+    out.extend_from_slice(&(padded as u32).to_be_bytes());
+    out.extend_from_slice(data);
+    out.resize(out.len() + padded - data.len(), 0);
+}
+
+fn layer_info(layers: &[PsdLayer], gray: bool, depth: u16) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut records = Vec::new();
+    let mut channel_data = Vec::new();
+    // negative: the first alpha channel of the composite is its transparency
+    records.extend_from_slice(&(-(layers.len() as i16)).to_be_bytes());
+    for layer in layers {
+        // layers without pixels are stored with empty bounds
+        let (width, height) = match (layer.image.width(), layer.image.height()) {
+            (width, height) if layer.kind.has_pixels() && width > 0 && height > 0 => {
+                (width, height)
+            }
+            _ => (0, 0),
+        };
+        let img = if gray {
+            DynamicImage::ImageLumaA16(layer.image.to_luma_alpha16())
+        } else {
+            DynamicImage::ImageRgba16(layer.image.to_rgba16())
+        };
+        let planes = planes(&img, depth, false);
+        records.extend_from_slice(&layer.top.to_be_bytes());
+        records.extend_from_slice(&layer.left.to_be_bytes());
+        records.extend_from_slice(&(layer.top + height as i32).to_be_bytes());
+        records.extend_from_slice(&(layer.left + width as i32).to_be_bytes());
+        records.extend_from_slice(&(planes.len() as u16).to_be_bytes());
+        // alpha is stored as channel -1 and comes first
+        let ids = (0..planes.len() as i16).map(|c| if c == 0 { -1 } else { c - 1 });
+        let alpha_first = std::iter::once(planes.len() - 1).chain(0..planes.len() - 1);
+        for (id, plane) in ids.zip(alpha_first) {
+            let mut data = 1u16.to_be_bytes().to_vec();
+            let rows: Vec<Vec<u8>> = if height == 0 {
+                Vec::new()
+            } else {
+                let plane = &planes[plane];
+                plane
+                    .chunks(plane.len() / height as usize)
+                    .map(pack_bits)
+                    .collect()
+            };
+            for row in &rows {
+                data.extend_from_slice(&(row.len() as u16).to_be_bytes());
+            }
+            for row in &rows {
+                data.extend_from_slice(row);
+            }
+            records.extend_from_slice(&id.to_be_bytes());
+            records.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            channel_data.extend_from_slice(&data);
+        }
+
+        let blend_mode = layer.blend_mode.as_bytes();
+        if blend_mode.is_empty() || blend_mode.len() > 4 {
+            return Err(format!("invalid psd blend mode: {:?}", layer.blend_mode).into());
+        }
+        records.extend_from_slice(b"8BIM");
+        records.extend_from_slice(&format!("{:<4}", layer.blend_mode).into_bytes());
+        records.push(layer.opacity);
+        records.push(0);
+        records.push(if layer.visible { 0 } else { 2 });
+        records.push(0);
+
+        let mut extra = Vec::new();
+        // layer mask and blending ranges
+        extra.extend_from_slice(&0u32.to_be_bytes());
+        extra.extend_from_slice(&0u32.to_be_bytes());
+        let name = match layer.kind {
+            PsdLayerKind::GroupEnd => "</Layer group>".to_string(),
+            _ => layer.name.clone(),
+        };
+        let ascii: Vec<u8> = name
+            .chars()
+            .map(|c| if c.is_ascii() { c as u8 } else { b'?' })
+            .take(255)
+            .collect();
+        extra.push(ascii.len() as u8);
+        extra.extend_from_slice(&ascii);
+        // pascal string padded to a multiple of 4 bytes
+        extra.resize(extra.len() + (4 - (ascii.len() + 1) % 4) % 4, 0);
+
+        let utf16: Vec<u16> = name.encode_utf16().collect();
+        let mut luni = (utf16.len() as u32).to_be_bytes().to_vec();
+        luni.extend(utf16.iter().flat_map(|c| c.to_be_bytes()));
+        extra.extend_from_slice(b"8BIMluni");
+        put_block(&mut extra, &luni);
+        let section = match layer.kind {
+            PsdLayerKind::Group => Some(1u32),
+            PsdLayerKind::GroupEnd => Some(3u32),
+            _ => None,
+        };
+        if let Some(section) = section {
+            extra.extend_from_slice(b"8BIMlsct");
+            put_block(&mut extra, &section.to_be_bytes());
+        }
+        records.extend_from_slice(&(extra.len() as u32).to_be_bytes());
+        records.extend_from_slice(&extra);
+    }
+    records.extend_from_slice(&channel_data);
+    Ok(records)
+}
+
+/// Splits the image into planar big-endian channels, color channels then alpha.
+///
+/// `matted` blends the colors over white like the merged image of photoshop.
+fn planes(img: &DynamicImage, depth: u16, matted: bool) -> Vec<Vec<u8>> {
+    let channels = img.color().channel_count() as usize;
+    let px = match img {
+        DynamicImage::ImageLumaA16(_) | DynamicImage::ImageRgba16(_) => img.as_bytes(),
+        _ => &[],
+    };
+    let samples: Vec<u16> = if px.is_empty() {
+        if channels == 2 {
+            img.to_luma_alpha16().into_raw()
+        } else {
+            img.to_rgba16().into_raw()
+        }
+    } else {
+        px.chunks_exact(2)
+            .map(|b| u16::from_ne_bytes([b[0], b[1]]))
+            .collect()
+    };
+    let mut planes = vec![Vec::new(); channels];
+    for pixel in samples.chunks_exact(channels) {
+        let alpha = pixel[channels - 1] as f32 / 65535.0;
+        for (c, plane) in planes.iter_mut().enumerate() {
+            let mut value = pixel[c];
+            if matted && c < channels - 1 {
+                value = (value as f32 * alpha + 65535.0 * (1.0 - alpha)).round() as u16;
+            }
+            if depth == 8 {
+                plane.push(((value as u32 + 128) / 257) as u8);
+            } else {
+                plane.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+    }
+    planes
+}
+
+/// Flattens the visible pixel layers bottom to top.
+fn composite(layers: &[PsdLayer], width: u32, height: u32, gray: bool) -> DynamicImage {
+    let channels = if gray { 2 } else { 4 };
+    let (w, h) = (width as usize, height as usize);
+    let mut canvas = vec![0f32; w * h * channels];
+
+    // a hidden group hides its content, groups open at the top and close at the bottom
+    let mut visible = vec![false; layers.len()];
+    let mut groups = vec![true];
+    for (i, layer) in layers.iter().enumerate().rev() {
+        let parent = *groups.last().unwrap_or(&true);
+        match layer.kind {
+            PsdLayerKind::Group => groups.push(parent && layer.visible),
+            PsdLayerKind::GroupEnd => {
+                groups.pop();
+            }
+            _ => visible[i] = parent && layer.visible,
+        }
+    }
+
+    for (layer, _) in layers.iter().zip(visible).filter(|(_, visible)| *visible) {
+        let src: Vec<f32> = if gray {
+            layer.image.to_luma_alpha32f().into_raw()
+        } else {
+            layer.image.to_rgba32f().into_raw()
+        };
+        let opacity = layer.opacity as f32 / 255.0;
+        let (lw, lh) = (layer.image.width() as i64, layer.image.height() as i64);
+        for ly in 0..lh {
+            let y = ly + layer.top as i64;
+            if y < 0 || y >= h as i64 {
+                continue;
+            }
+            for lx in 0..lw {
+                let x = lx + layer.left as i64;
+                if x < 0 || x >= w as i64 {
+                    continue;
+                }
+                let s = &src[(ly * lw + lx) as usize * channels..][..channels];
+                let d = &mut canvas[(y as usize * w + x as usize) * channels..][..channels];
+                blend_pixel(&layer.blend_mode, s, d, opacity);
+            }
+        }
+    }
+
+    let px: Vec<u16> = canvas
+        .iter()
+        .map(|v| (v.clamp(0.0, 1.0) * 65535.0).round() as u16)
+        .collect();
+    if gray {
+        DynamicImage::ImageLumaA16(ImageBuffer::from_raw(width, height, px).unwrap_or_default())
+    } else {
+        DynamicImage::ImageRgba16(ImageBuffer::from_raw(width, height, px).unwrap_or_default())
+    }
+}
+
+/// Source-over compositing with the separable photoshop blend modes, other modes
+/// fall back to normal.
+fn blend_pixel(mode: &str, src: &[f32], dst: &mut [f32], opacity: f32) {
+    let last = src.len() - 1;
+    let sa = src[last].clamp(0.0, 1.0) * opacity;
+    let da = dst[last];
+    if sa <= 0.0 {
+        return;
+    }
+    let out_a = sa + da * (1.0 - sa);
+    for c in 0..last {
+        let (cs, cb) = (src[c].clamp(0.0, 1.0), dst[c]);
+        let mixed = match mode {
+            "mul" => cs * cb,
+            "scrn" => cs + cb - cs * cb,
+            "dark" => cs.min(cb),
+            "lite" => cs.max(cb),
+            "diff" => (cs - cb).abs(),
+            "lddg" => (cs + cb).min(1.0),
+            "over" if cb <= 0.5 => 2.0 * cs * cb,
+            "over" => 1.0 - 2.0 * (1.0 - cs) * (1.0 - cb),
+            _ => cs,
+        };
+        // the blend only applies where the backdrop is opaque
+        let cs = (1.0 - da) * cs + da * mixed;
+        dst[c] = (sa * cs + da * cb * (1.0 - sa)) / out_a;
+    }
+    dst[last] = out_a;
+}
+
+/// PackBits compression of one row.
+fn pack_bits(row: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(row.len() + row.len() / 128 + 1);
+    let mut i = 0;
+    while i < row.len() {
+        let mut run = 1;
+        while i + run < row.len() && run < 128 && row[i + run] == row[i] {
+            run += 1;
+        }
+        if run >= 2 {
+            out.push((257 - run) as u8);
+            out.push(row[i]);
+            i += run;
+            continue;
+        }
+        let start = i;
+        while i < row.len() && i - start < 128 {
+            if i + 1 < row.len() && row[i] == row[i + 1] {
+                break;
+            }
+            i += 1;
+        }
+        out.push((i - start - 1) as u8);
+        out.extend_from_slice(&row[start..i]);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::image::psd::PsdFile;
+    use image::{Rgba, RgbaImage};
+
+    fn layer(name: &str, image: DynamicImage, top: i32, left: i32) -> PsdLayer {
+        PsdLayer {
+            name: name.to_string(),
+            kind: PsdLayerKind::Pixel,
+            visible: true,
+            opacity: 255,
+            blend_mode: "norm".to_string(),
+            top,
+            left,
+            image,
+            channels: 4,
+        }
+    }
+
+    fn round_trip(depth: u16, base: DynamicImage, ink: DynamicImage) {
+        let mut ink = layer("Ink ✓", ink, 2, 1);
+        ink.visible = false;
+        ink.opacity = 128;
+        ink.blend_mode = "mul".to_string();
+        let layers = [layer("base", base, 0, 0), ink];
+        let psd = write_psd(&layers, None, depth).unwrap();
+
+        let file = PsdFile::parse(&psd).unwrap();
+        assert_eq!((file.header.width, file.header.height), (4, 4));
+        assert_eq!(file.header.depth, depth);
+        let read = file.decode_layers().unwrap();
+        assert_eq!(read.len(), 2);
+        for (read, layer) in read.iter().zip(&layers) {
+            assert_eq!(read.name, layer.name);
+            assert_eq!(read.visible, layer.visible);
+            assert_eq!(read.opacity, layer.opacity);
+            assert_eq!(read.blend_mode, layer.blend_mode);
+            assert_eq!((read.top, read.left), (layer.top, layer.left));
+            assert_eq!(read.image.to_rgba16(), layer.image.to_rgba16());
+        }
+        // the hidden layer is left out of the composite
+        let (composite, _) = file.decode().unwrap();
+        assert_eq!(composite.to_rgba16(), layers[0].image.to_rgba16());
+    }
+
+    #[test]
+    fn psd_8bit_round_trip() {
+        let base = RgbaImage::from_fn(4, 4, |x, y| Rgba([x as u8 * 60, y as u8 * 60, 90, 255]));
+        let ink = RgbaImage::from_fn(3, 2, |x, y| Rgba([10, 20, 30, 100 + (x + y) as u8]));
+        round_trip(8, base.into(), ink.into());
+    }
+
+    #[test]
+    fn empty_layers_are_written() {
+        let base = RgbaImage::from_pixel(4, 4, Rgba([1, 2, 3, 255]));
+        let empty = |w, h| layer("empty", RgbaImage::new(w, h).into(), 0, 0);
+        let layers = [layer("base", base.into(), 0, 0), empty(0, 3), empty(3, 0)];
+        let psd = write_psd(&layers, None, 8).unwrap();
+        let read = PsdFile::parse(&psd).unwrap().decode_layers().unwrap();
+        assert_eq!(read.len(), 3);
+        assert_eq!((read[1].image.width(), read[2].image.height()), (0, 0));
+    }
+
+    #[test]
+    fn bad_layer_bounds_fail() {
+        let base = RgbaImage::from_pixel(4, 4, Rgba([1, 2, 3, 255]));
+        let psd = write_psd(&[layer("base", base.into(), 0, 0)], None, 8).unwrap();
+        // header, empty color data and resources, section and info lengths, layer count
+        let rect = 26 + 8 + 8 + 2;
+        for (left, right) in [(1i32, i32::MIN), (0, 30001), (0, 20000)] {
+            let mut psd = psd.clone();
+            psd[rect + 4..rect + 8].copy_from_slice(&left.to_be_bytes());
+            psd[rect + 12..rect + 16].copy_from_slice(&right.to_be_bytes());
+            assert!(PsdFile::parse(&psd).unwrap().decode_layers().is_err());
+        }
+    }
+
+    #[test]
+    fn psd_16bit_round_trip() {
+        let base = ImageBuffer::from_fn(4, 4, |x, y| {
+            Rgba([x as u16 * 12345, y as u16 * 4321, 1000, 65535])
+        });
+        let ink = ImageBuffer::from_fn(3, 2, |x, y| Rgba([7, 70, 700, 30000 + (x + y) as u16]));
+        round_trip(
+            16,
+            DynamicImage::ImageRgba16(base),
+            DynamicImage::ImageRgba16(ink),
+        );
+    }
+}
@@ -0,0 +1,387 @@
+use std::error::Error;
+use std::io::Cursor;
+use std::ops::Range;
+use std::path::Path;
+
+use filebuffer::FileBuffer;
+use image::{DynamicImage, ImageFormat};
+use ndarray::ArrayD;
+use tiff::decoder::{Decoder as TiffDecoder, DecodingResult};
+use tiff::tags::Tag;
+use tiff::ColorType as TiffColor;
+
+use crate::utils::core::convert::buffer;
+use crate::utils::core::enums::ImgColor;
+use crate::utils::image::decode::{img_open, img_to_f32, img_to_u16, img_to_u8};
+use crate::utils::image::native::{cmyk_jpeg, NativeImage};
+use crate::utils::image::psd::{gray_to_rgb, PsdFile, PSD_MAGIC};
+
+// Region of interest reads for very tall pages: psd rows, png rows and tiff tiles
+// or strips outside the rectangle are never unpacked, other formats are decoded in
+// full and cropped.
+
+/// Rectangle of an image in stored pixel coordinates (before exif orientation).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Region {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Region {
+    pub fn full(width: usize, height: usize) -> Region {
+        Region {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        }
+    }
+
+    pub fn rows(&self) -> Range<usize> {
+        self.y..self.y + self.height
+    }
+
+    pub fn columns(&self) -> Range<usize> {
+        self.x..self.x + self.width
+    }
+
+    /// Checks that the region is not empty and lies inside a `width` x `height` image.
+    pub fn check(&self, width: usize, height: usize) -> Result<(), Box<dyn Error>> {
+        if self.width == 0
+            || self.height == 0
+            || self
+                .x
+                .checked_add(self.width)
+                .is_none_or(|right| right > width)
+            || self
+                .y
+                .checked_add(self.height)
+                .is_none_or(|bottom| bottom > height)
+        {
+            return Err(format!(
+                "region {}x{} at ({}, {}) is outside the {}x{} image",
+                self.width, self.height, self.x, self.y, width, height
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// Interleaved samples of a tiff region in the type the chunks decode to.
+enum Samples {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+    F32(Vec<f32>),
+}
+
+/// Copies the part of a decoded chunk at `origin` that overlaps `region` into the
+/// region samples `out`.
+fn paste_chunk<T: Copy>(
+    data: &[T],
+    data_width: usize,
+    origin: (usize, usize),
+    out: &mut [T],
+    region: Region,
+    channels: usize,
+) {
+    let (ox, oy) = origin;
+    let x0 = ox.max(region.x);
+    let x1 = (ox + data_width).min(region.x + region.width);
+    let len = (x1 - x0) * channels;
+    for (r, row) in data.chunks_exact(data_width * channels).enumerate() {
+        let y = oy + r;
+        if region.rows().contains(&y) {
+            let dst = ((y - region.y) * region.width + x0 - region.x) * channels;
+            out[dst..dst + len].copy_from_slice(&row[(x0 - ox) * channels..][..len]);
+        }
+    }
+}
+
+/// Decodes only the tiles (or strips) of a tiff that overlap `region`.
+///
+/// # Returns
+/// None for layouts left to the full decoder: planar, palette, cmyk, ycbcr,
+/// white-is-zero, signed or below 8 bit files.
+fn tiff_region(bytes: &[u8], region: Region) -> Result<Option<(DynamicImage, u8)>, Box<dyn Error>> {
+    let mut decoder = TiffDecoder::new(Cursor::new(bytes))?;
+    let (width, height) = decoder.dimensions()?;
+    region.check(width as usize, height as usize)?;
+    let (channels, bits) = match decoder.colortype()? {
+        TiffColor::Gray(bits) => (1, bits),
+        TiffColor::GrayA(bits) => (2, bits),
+        TiffColor::RGB(bits) => (3, bits),
+        TiffColor::RGBA(bits) => (4, bits),
+        _ => return Ok(None),
+    };
+    let planar = decoder.find_tag_unsigned::<u16>(Tag::PlanarConfiguration)? == Some(2);
+    let inverted = decoder.find_tag_unsigned::<u16>(Tag::PhotometricInterpretation)? == Some(0);
+    if !matches!(bits, 8 | 16 | 32) || planar || inverted {
+        return Ok(None);
+    }
+
+    let (chunk_width, chunk_height) = decoder.chunk_dimensions();
+    let (chunk_width, chunk_height) = (chunk_width as usize, chunk_height as usize);
+    let across = (width as usize).div_ceil(chunk_width);
+    let len = region.width * region.height * channels;
+    let mut samples = None;
+    for cy in region.y / chunk_height..=(region.y + region.height - 1) / chunk_height {
+        for cx in region.x / chunk_width..=(region.x + region.width - 1) / chunk_width {
+            let index = (cy * across + cx) as u32;
+            let data_width = decoder.chunk_data_dimensions(index).0 as usize;
+            let origin = (cx * chunk_width, cy * chunk_height);
+
+            macro_rules! paste {
+                ($variant:ident, $data:expr) => {{
+                    let out = samples
+                        .get_or_insert_with(|| Samples::$variant(vec![Default::default(); len]));
+                    let Samples::$variant(out) = out else {
+                        return Err("tiff chunks have mixed sample types".into());
+                    };
+                    paste_chunk(&$data, data_width, origin, out, region, channels);
+                }};
+            }
+            match decoder.read_chunk(index)? {
+                DecodingResult::U8(data) => paste!(U8, data),
+                DecodingResult::U16(data) => paste!(U16, data),
+                DecodingResult::F32(data) => paste!(F32, data),
+                _ => return Ok(None),
+            }
+        }
+    }
+
+    let (w, h) = (region.width as u32, region.height as u32);
+    let img = match samples.ok_or("tiff has no image data")? {
+        Samples::U8(px) => match channels {
+            1 => DynamicImage::ImageLuma8(buffer(w, h, px)?),
+            2 => DynamicImage::ImageLumaA8(buffer(w, h, px)?),
+            3 => DynamicImage::ImageRgb8(buffer(w, h, px)?),
+            _ => DynamicImage::ImageRgba8(buffer(w, h, px)?),
+        },
+        Samples::U16(px) => match channels {
+            1 => DynamicImage::ImageLuma16(buffer(w, h, px)?),
+            2 => DynamicImage::ImageLumaA16(buffer(w, h, px)?),
+            3 => DynamicImage::ImageRgb16(buffer(w, h, px)?),
+            _ => DynamicImage::ImageRgba16(buffer(w, h, px)?),
+        },
+        Samples::F32(px) => match channels {
+            1 => DynamicImage::ImageRgb32F(buffer(w, h, gray_to_rgb(&px, 1))?),
+            2 => DynamicImage::ImageRgba32F(buffer(w, h, gray_to_rgb(&px, 2))?),
+            3 => DynamicImage::ImageRgb32F(buffer(w, h, px)?),
+            _ => DynamicImage::ImageRgba32F(buffer(w, h, px)?),
+        },
+    };
+    Ok(Some((img, channels as u8)))
+}
+
+/// Wraps rows of expanded png samples (8 bit or big-endian 16 bit) into an image.
+pub(crate) fn png_image(
+    px: Vec<u8>,
+    w: u32,
+    h: u32,
+    channels: usize,
+    sixteen: bool,
+) -> Result<DynamicImage, Box<dyn Error>> {
+    Ok(if sixteen {
+        let px = px
+            .chunks_exact(2)
+            .map(|v| u16::from_be_bytes([v[0], v[1]]))
+            .collect();
+        match channels {
+            1 => DynamicImage::ImageLuma16(buffer(w, h, px)?),
+            2 => DynamicImage::ImageLumaA16(buffer(w, h, px)?),
+            3 => DynamicImage::ImageRgb16(buffer(w, h, px)?),
+            _ => DynamicImage::ImageRgba16(buffer(w, h, px)?),
+        }
+    } else {
+        match channels {
+            1 => DynamicImage::ImageLuma8(buffer(w, h, px)?),
+            2 => DynamicImage::ImageLumaA8(buffer(w, h, px)?),
+            3 => DynamicImage::ImageRgb8(buffer(w, h, px)?),
+            _ => DynamicImage::ImageRgba8(buffer(w, h, px)?),
+        }
+    })
+}
+
+/// Streams the rows of a png up to the bottom of `region`, rows below it are never
+/// inflated.
+///
+/// # Returns
+/// None for interlaced files, their passes span the whole image.
+fn png_region(bytes: &[u8], region: Region) -> Result<Option<(DynamicImage, u8)>, Box<dyn Error>> {
+    let mut decoder = png::Decoder::new(Cursor::new(bytes));
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info()?;
+    let info = reader.info();
+    region.check(info.width as usize, info.height as usize)?;
+    if info.interlaced {
+        return Ok(None);
+    }
+    let (color, depth) = reader.output_color_type();
+    let channels = color.samples();
+    let sixteen = depth == png::BitDepth::Sixteen;
+    let pixel_bytes = channels * (1 + sixteen as usize);
+    let mut px = Vec::with_capacity(region.width * region.height * pixel_bytes);
+    for y in 0..region.y + region.height {
+        let row = reader.next_row()?.ok_or("png is truncated")?;
+        if y >= region.y {
+            px.extend_from_slice(
+                &row.data()[region.x * pixel_bytes..][..region.width * pixel_bytes],
+            );
+        }
+    }
+
+    let (w, h) = (region.width as u32, region.height as u32);
+    let img = png_image(px, w, h, channels, sixteen)?;
+    Ok(Some((img, channels as u8)))
+}
+
+/// Decodes the `region` rectangle of an image like `img_open` (without orientation
+/// and icc conversion), psd, png and tiff only decode the rows or tiles it covers.
+///
+/// # Returns
+/// The region in the source bit depth and the native channel count of the file.
+pub(crate) fn region_open(
+    bytes: &[u8],
+    region: Region,
+) -> Result<(DynamicImage, u8), Box<dyn Error>> {
+    if bytes.starts_with(&PSD_MAGIC) {
+        return PsdFile::parse(bytes)?.decode_region(region);
+    }
+    let partial = match image::guess_format(bytes) {
+        Ok(ImageFormat::Png) => png_region(bytes, region)?,
+        Ok(ImageFormat::Tiff) => tiff_region(bytes, region)?,
+        _ => None,
+    };
+    if let Some(partial) = partial {
+        return Ok(partial);
+    }
+    let (img, channels) = img_open(bytes, false, false, 1)?;
+    region.check(img.width() as usize, img.height() as usize)?;
+    let (x, y, w, h) = (region.x, region.y, region.width, region.height);
+    Ok((
+        img.crop_imm(x as u32, y as u32, w as u32, h as u32),
+        channels,
+    ))
+}
+
+/// Native samples of the `region` of cmyk, lab and multichannel psd files and
+/// cmyk jpegs, None for every other image.
+fn region_native(bytes: &[u8], region: Region) -> Result<Option<NativeImage>, Box<dyn Error>> {
+    if bytes.starts_with(&PSD_MAGIC) {
+        return PsdFile::parse(bytes)?.decode_native_region(region);
+    }
+    cmyk_jpeg(bytes)?
+        .map(|native| native.crop(region))
+        .transpose()
+}
+
+pub fn all_read_region_u8(
+    path: &Path,
+    region: Region,
+    mode: ImgColor,
+) -> Result<ArrayD<u8>, Box<dyn Error>> {
+    let bytes = FileBuffer::open(path).map_err(|err| Box::new(err) as Box<dyn Error>)?;
+    if matches!(mode, ImgColor::DYNAMIC) {
+        if let Some(native) = region_native(&bytes, region)? {
+            return native.to_array(None, |v| (v * 255.0).round() as u8);
+        }
+    }
+    let (img, native) = region_open(&bytes, region)?;
+    Ok(img_to_u8(img, native, mode))
+}
+
+pub fn all_read_region_u16(
+    path: &Path,
+    region: Region,
+    mode: ImgColor,
+) -> Result<ArrayD<u16>, Box<dyn Error>> {
+    let bytes = FileBuffer::open(path).map_err(|err| Box::new(err) as Box<dyn Error>)?;
+    if matches!(mode, ImgColor::DYNAMIC) {
+        if let Some(native) = region_native(&bytes, region)? {
+            return native.to_array(None, |v| (v * 65535.0).round() as u16);
+        }
+    }
+    let (img, native) = region_open(&bytes, region)?;
+    Ok(img_to_u16(img, native, mode))
+}
+
+pub fn all_read_region_f32(
+    path: &Path,
+    region: Region,
+    mode: ImgColor,
+) -> Result<ArrayD<f32>, Box<dyn Error>> {
+    let bytes = FileBuffer::open(path).map_err(|err| Box::new(err) as Box<dyn Error>)?;
+    if matches!(mode, ImgColor::DYNAMIC) {
+        if let Some(native) = region_native(&bytes, region)? {
+            return native.to_array(None, |v| v);
+        }
+    }
+    let (img, native) = region_open(&bytes, region)?;
+    Ok(img_to_f32(img, native, mode))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 20x18 rgb, 8 bit, 16x16 tiles: (x * 10, y * 10, (x + y) * 5)
+    const RGB8_TILED: &[u8] = include_bytes!("../../../tests/fixtures/rgb8_tiled.tif");
+    // 2x2 rgb, 16 bit, rle: red, green, 128 gray, (16, 32, 240) in 8 bit terms
+    const RGB16_RLE: &[u8] = include_bytes!("../../../tests/fixtures/rgb16_rle.psd");
+
+    #[test]
+    fn tiff_region_across_tiles() {
+        let region = Region {
+            x: 14,
+            y: 13,
+            width: 5,
+            height: 4,
+        };
+        let (img, channels) = tiff_region(RGB8_TILED, region).unwrap().unwrap();
+        assert_eq!((img.width(), img.height(), channels), (5, 4, 3));
+        let img = img.to_rgb8();
+        for (x, y, px) in img.enumerate_pixels() {
+            let (x, y) = (x as usize + 14, y as usize + 13);
+            assert_eq!(px.0, [x * 10, y * 10, (x + y) * 5].map(|v| v as u8));
+        }
+        let outside = Region { y: 15, ..region };
+        assert!(tiff_region(RGB8_TILED, outside).is_err());
+    }
+
+    #[test]
+    fn psd_region_matches_crop() {
+        let (full, _) = region_open(RGB16_RLE, Region::full(2, 2)).unwrap();
+        let region = Region {
+            x: 1,
+            y: 0,
+            width: 1,
+            height: 2,
+        };
+        let (img, channels) = region_open(RGB16_RLE, region).unwrap();
+        assert_eq!(channels, 3);
+        assert_eq!(img.to_rgb16(), full.crop_imm(1, 0, 1, 2).to_rgb16());
+    }
+
+    #[test]
+    fn overflowing_region_fails() {
+        let region = Region {
+            x: usize::MAX,
+            y: 0,
+            width: 2,
+            height: 1,
+        };
+        assert!(region.check(4, 4).is_err());
+        assert!(region_open(RGB16_RLE, region).is_err());
+        assert!(tiff_region(
+            RGB8_TILED,
+            Region {
+                y: usize::MAX,
+                ..region
+            }
+        )
+        .is_err());
+    }
+}
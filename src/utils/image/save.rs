@@ -1,40 +1,445 @@
 use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Cursor, Seek, Write};
 use std::path::Path;
 
-use image::{ImageBuffer, Luma, RgbImage};
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::codecs::webp::WebPEncoder;
+use image::{DynamicImage, ImageEncoder, ImageFormat, Luma, LumaA, Rgb, Rgba};
+use jpeg_encoder::{ColorType, SamplingFactor};
+use pyo3::{pyclass, pymethods};
+
+use crate::utils::core::convert::buffer;
+use crate::utils::core::enums::{ChromaSubsampling, PngFilter, SaveFormat, TypeDither};
+use crate::utils::image::palette::{median_cut, pack_rows, palette_indices};
+
+/// Encoder settings for `save`, options that do not apply to the output format are ignored.
+#[pyclass]
+#[derive(Clone, Default)]
+pub struct SaveOptions {
+    /// jpeg and lossy webp quality 1-100, 75 by default
+    #[pyo3(get, set)]
+    pub quality: Option<u8>,
+    /// jpeg chroma subsampling, 4:2:0 by default
+    #[pyo3(get, set)]
+    pub subsampling: Option<ChromaSubsampling>,
+    /// png compression level 0 (none) - 9 (best), fast by default
+    #[pyo3(get, set)]
+    pub compression: Option<u8>,
+    /// png row filter, adaptive by default
+    #[pyo3(get, set)]
+    pub png_filter: Option<PngFilter>,
+    /// webp lossless encoding, true by default
+    #[pyo3(get, set)]
+    pub lossless: Option<bool>,
+    /// output bits per channel 8 or 16 (png, tiff), 16 for u16 arrays and 8 otherwise by default
+    #[pyo3(get, set)]
+    pub bit_depth: Option<u8>,
+    /// dithering when float arrays are quantized, none (plain rounding) by default
+    #[pyo3(get, set)]
+    pub dither: Option<TypeDither>,
+    /// write binary gray images (only black and white) as 1 bit png or g4 tiff, false by default
+    #[pyo3(get, set)]
+    pub bilevel: Option<bool>,
+    /// write an indexed png with an automatic palette (rgba with a trns chunk for
+    /// images with alpha), false by default
+    #[pyo3(get, set)]
+    pub indexed: Option<bool>,
+    /// rgb palette for an indexed png, pixels are mapped to the nearest entry, images
+    /// with alpha need the automatic palette
+    #[pyo3(get, set)]
+    pub palette: Option<Vec<(u8, u8, u8)>>,
+    /// maximum size 2-256 of the automatic palette, 256 by default
+    #[pyo3(get, set)]
+    pub colors: Option<u16>,
+}
+
+#[pymethods]
+impl SaveOptions {
+    #[new]
+    #[pyo3(signature = (quality=None, subsampling=None, compression=None, png_filter=None, lossless=None, bit_depth=None, dither=None, bilevel=None, indexed=None, palette=None, colors=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        quality: Option<u8>,
+        subsampling: Option<ChromaSubsampling>,
+        compression: Option<u8>,
+        png_filter: Option<PngFilter>,
+        lossless: Option<bool>,
+        bit_depth: Option<u8>,
+        dither: Option<TypeDither>,
+        bilevel: Option<bool>,
+        indexed: Option<bool>,
+        palette: Option<Vec<(u8, u8, u8)>>,
+        colors: Option<u16>,
+    ) -> Self {
+        SaveOptions {
+            quality,
+            subsampling,
+            compression,
+            png_filter,
+            lossless,
+            bit_depth,
+            dither,
+            bilevel,
+            indexed,
+            palette,
+            colors,
+        }
+    }
+}
+
+impl From<SaveFormat> for ImageFormat {
+    fn from(format: SaveFormat) -> Self {
+        match format {
+            SaveFormat::PNG => ImageFormat::Png,
+            SaveFormat::JPEG => ImageFormat::Jpeg,
+            SaveFormat::WEBP => ImageFormat::WebP,
+            SaveFormat::TIFF => ImageFormat::Tiff,
+            SaveFormat::BMP => ImageFormat::Bmp,
+        }
+    }
+}
+
+/// Samples of the array to save, u16 keeps the precision for 16 bit output.
+pub enum ImgVec {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+}
+
+pub(crate) fn vec_to_img(
+    vec_img: ImgVec,
+    shape: &[usize],
+    bit_depth: Option<u8>,
+) -> Result<DynamicImage, Box<dyn Error>> {
+    let channels = match shape.len() {
+        2 => 1,
+        3 => shape[2],
+        _ => return Err("The array must be 2D or 3D".into()),
+    };
+    let (width, height) = (shape[1] as u32, shape[0] as u32);
+    let img = match (vec_img, channels) {
+        (ImgVec::U8(px), 1) => DynamicImage::ImageLuma8(buffer::<Luma<u8>>(width, height, px)?),
+        (ImgVec::U8(px), 2) => DynamicImage::ImageLumaA8(buffer::<LumaA<u8>>(width, height, px)?),
+        (ImgVec::U8(px), 3) => DynamicImage::ImageRgb8(buffer::<Rgb<u8>>(width, height, px)?),
+        (ImgVec::U8(px), 4) => DynamicImage::ImageRgba8(buffer::<Rgba<u8>>(width, height, px)?),
+        (ImgVec::U16(px), 1) => DynamicImage::ImageLuma16(buffer::<Luma<u16>>(width, height, px)?),
+        (ImgVec::U16(px), 2) => {
+            DynamicImage::ImageLumaA16(buffer::<LumaA<u16>>(width, height, px)?)
+        }
+        (ImgVec::U16(px), 3) => DynamicImage::ImageRgb16(buffer::<Rgb<u16>>(width, height, px)?),
+        (ImgVec::U16(px), 4) => DynamicImage::ImageRgba16(buffer::<Rgba<u16>>(width, height, px)?),
+        _ => return Err("color channel error".into()),
+    };
+    match bit_depth {
+        Some(bit_depth) => with_bit_depth(img, bit_depth),
+        None => Ok(img),
+    }
+}
+
+/// Converts the image to the requested bits per channel keeping the channel layout.
+fn with_bit_depth(img: DynamicImage, bit_depth: u8) -> Result<DynamicImage, Box<dyn Error>> {
+    let channels = img.color().channel_count();
+    let img = match (bit_depth, channels) {
+        (8, _) if img.color().bytes_per_pixel() == channels => img,
+        (16, _) if img.color().bytes_per_pixel() == channels * 2 => img,
+        (8, 1) => DynamicImage::ImageLuma8(img.to_luma8()),
+        (8, 2) => DynamicImage::ImageLumaA8(img.to_luma_alpha8()),
+        (8, 3) => DynamicImage::ImageRgb8(img.to_rgb8()),
+        (8, _) => DynamicImage::ImageRgba8(img.to_rgba8()),
+        (16, 1) => DynamicImage::ImageLuma16(img.to_luma16()),
+        (16, 2) => DynamicImage::ImageLumaA16(img.to_luma_alpha16()),
+        (16, 3) => DynamicImage::ImageRgb16(img.to_rgb16()),
+        (16, _) => DynamicImage::ImageRgba16(img.to_rgba16()),
+        _ => return Err("bit_depth must be 8 or 16".into()),
+    };
+    Ok(img)
+}
 
 pub fn save_img_vec(
-    vec_img: &[u8],
+    vec_img: ImgVec,
     shape: &[usize],
     out_path: &Path,
+    format: Option<SaveFormat>,
+    options: &SaveOptions,
 ) -> Result<(), Box<dyn Error>> {
-    match shape.len() {
-        2 => {
-            let img = ImageBuffer::from_fn(shape[1] as u32, shape[0] as u32, move |x, y| {
-                Luma([vec_img[(y * shape[1] as u32 + x) as usize]])
-            });
-            img.save(Path::new(&out_path))?;
+    let img = vec_to_img(vec_img, shape, options.bit_depth)?;
+    let format = match format {
+        Some(format) => format.into(),
+        None => ImageFormat::from_path(out_path)?,
+    };
+    let mut file = BufWriter::new(File::create(out_path)?);
+    write_img(img, format, options, &mut file)?;
+    file.flush()?;
+    Ok(())
+}
+
+pub fn encode_img_vec(
+    vec_img: ImgVec,
+    shape: &[usize],
+    format: SaveFormat,
+    options: &SaveOptions,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let img = vec_to_img(vec_img, shape, options.bit_depth)?;
+    let mut cursor = Cursor::new(Vec::new());
+    write_img(img, format.into(), options, &mut cursor)?;
+    Ok(cursor.into_inner())
+}
+
+/// Encodes the image with the encoder options of the format, formats without
+/// options go through the image crate defaults.
+pub fn write_img<W: Write + Seek>(
+    img: DynamicImage,
+    format: ImageFormat,
+    options: &SaveOptions,
+    writer: &mut W,
+) -> Result<(), Box<dyn Error>> {
+    if options.bilevel.unwrap_or(false)
+        && matches!(format, ImageFormat::Png | ImageFormat::Tiff)
+        && is_bilevel(&img)
+    {
+        return write_bilevel(&img, format, options, writer);
+    }
+    if options.indexed.unwrap_or(false) || options.palette.is_some() {
+        if format != ImageFormat::Png {
+            return Err("indexed palette output is only supported for png".into());
         }
-        3 => match shape[2] {
-            3 => {
-                let img = RgbImage::from_raw(shape[1] as u32, shape[0] as u32, vec_img.to_vec())
-                    .ok_or("container should have the right size for the image dimensions")?;
-                img.save(Path::new(&out_path))?;
-            }
-            1 => {
-                let img = ImageBuffer::from_fn(shape[1] as u32, shape[0] as u32, move |x, y| {
-                    Luma([vec_img[(y * shape[1] as u32 + x) as usize]])
-                });
-                img.save(Path::new(&out_path))?;
+        return write_indexed_png(&img, options, writer);
+    }
+    match format {
+        ImageFormat::Png => {
+            let compression = match options.compression {
+                None => CompressionType::Fast,
+                Some(0) => CompressionType::Uncompressed,
+                Some(level) => CompressionType::Level(level.min(9)),
+            };
+            let filter = match options.png_filter.unwrap_or(PngFilter::Adaptive) {
+                PngFilter::NoFilter => FilterType::NoFilter,
+                PngFilter::Sub => FilterType::Sub,
+                PngFilter::Up => FilterType::Up,
+                PngFilter::Avg => FilterType::Avg,
+                PngFilter::Paeth => FilterType::Paeth,
+                PngFilter::Adaptive => FilterType::Adaptive,
+            };
+            PngEncoder::new_with_quality(writer, compression, filter).write_image(
+                img.as_bytes(),
+                img.width(),
+                img.height(),
+                img.color().into(),
+            )?;
+        }
+        ImageFormat::Jpeg => {
+            let (width, height) = (
+                u16::try_from(img.width()).map_err(|_| "jpeg width is limited to 65535")?,
+                u16::try_from(img.height()).map_err(|_| "jpeg height is limited to 65535")?,
+            );
+            let mut encoder =
+                jpeg_encoder::Encoder::new(writer, options.quality.unwrap_or(75).clamp(1, 100));
+            encoder.set_sampling_factor(
+                match options.subsampling.unwrap_or(ChromaSubsampling::YUV420) {
+                    ChromaSubsampling::YUV444 => SamplingFactor::R_4_4_4,
+                    ChromaSubsampling::YUV422 => SamplingFactor::R_4_2_2,
+                    ChromaSubsampling::YUV420 => SamplingFactor::R_4_2_0,
+                },
+            );
+            if img.color().has_color() {
+                encoder.encode(&img.to_rgb8(), width, height, ColorType::Rgb)?;
+            } else {
+                encoder.encode(&img.to_luma8(), width, height, ColorType::Luma)?;
             }
-            _ => {
-                return Err("color channel error".into());
+        }
+        ImageFormat::WebP => {
+            if options.lossless.unwrap_or(true) {
+                let img = if img.color().has_alpha() {
+                    DynamicImage::ImageRgba8(img.to_rgba8())
+                } else {
+                    DynamicImage::ImageRgb8(img.to_rgb8())
+                };
+                WebPEncoder::new_lossless(writer).write_image(
+                    img.as_bytes(),
+                    img.width(),
+                    img.height(),
+                    img.color().into(),
+                )?;
+            } else {
+                let quality = options.quality.unwrap_or(75).min(100) as f32;
+                let (width, height) = (img.width(), img.height());
+                let data = if img.color().has_alpha() {
+                    let rgba = img.to_rgba8();
+                    webp::Encoder::from_rgba(&rgba, width, height).encode_simple(false, quality)
+                } else {
+                    let rgb = img.to_rgb8();
+                    webp::Encoder::from_rgb(&rgb, width, height).encode_simple(false, quality)
+                }
+                .map_err(|err| format!("webp encoding failed: {:?}", err))?;
+                writer.write_all(&data)?;
             }
-        },
+        }
+        // only png and tiff keep 16 bit samples
+        ImageFormat::Tiff => img.write_to(writer, format)?,
+        format => with_bit_depth(img, 8)?.write_to(writer, format)?,
+    }
+    Ok(())
+}
 
-        _ => {
-            return Err("The array must be 2D or 3D".into());
+fn png_encoder<W: Write>(
+    writer: W,
+    width: u32,
+    height: u32,
+    options: &SaveOptions,
+) -> png::Encoder<'static, W> {
+    let mut encoder = png::Encoder::new(writer, width, height);
+    match options.compression {
+        None => encoder.set_compression(png::Compression::Fast),
+        Some(0) => encoder.set_compression(png::Compression::NoCompression),
+        Some(level) => {
+            encoder.set_deflate_compression(png::DeflateCompression::Level(level.min(9)))
         }
     }
+    encoder.set_filter(match options.png_filter.unwrap_or(PngFilter::Adaptive) {
+        PngFilter::NoFilter => png::Filter::NoFilter,
+        PngFilter::Sub => png::Filter::Sub,
+        PngFilter::Up => png::Filter::Up,
+        PngFilter::Avg => png::Filter::Avg,
+        PngFilter::Paeth => png::Filter::Paeth,
+        PngFilter::Adaptive => png::Filter::Adaptive,
+    });
+    encoder
+}
+
+/// Gray image whose samples are only black or white.
+fn is_bilevel(img: &DynamicImage) -> bool {
+    match img {
+        DynamicImage::ImageLuma8(img) => img.iter().all(|&v| v == 0 || v == u8::MAX),
+        DynamicImage::ImageLuma16(img) => img.iter().all(|&v| v == 0 || v == u16::MAX),
+        _ => false,
+    }
+}
+
+fn write_bilevel<W: Write + Seek>(
+    img: &DynamicImage,
+    format: ImageFormat,
+    options: &SaveOptions,
+    writer: &mut W,
+) -> Result<(), Box<dyn Error>> {
+    let (width, height) = (img.width(), img.height());
+    let white: Vec<bool> = img.to_luma8().iter().map(|&v| v != 0).collect();
+    if format == ImageFormat::Tiff {
+        let line_width = u16::try_from(width).map_err(|_| "g4 tiff width is limited to 65535")?;
+        let mut encoder = fax::encoder::Encoder::new(fax::VecWriter::new());
+        for row in white.chunks(width.max(1) as usize) {
+            let line = row.iter().map(|&white| {
+                if white {
+                    fax::Color::White
+                } else {
+                    fax::Color::Black
+                }
+            });
+            encoder
+                .encode_line(line, line_width)
+                .map_err(|_| "g4 encoding failed")?;
+        }
+        let data = encoder.finish().map_err(|_| "g4 encoding failed")?.finish();
+        writer.write_all(&fax::tiff::wrap(&data, width, height))?;
+        return Ok(());
+    }
+    let samples: Vec<u8> = white.iter().map(|&white| white as u8).collect();
+    let mut encoder = png_encoder(writer, width, height, options);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::One);
+    encoder
+        .write_header()?
+        .write_image_data(&pack_rows(&samples, width as usize, 1))?;
+    Ok(())
+}
+
+fn write_indexed_png<W: Write>(
+    img: &DynamicImage,
+    options: &SaveOptions,
+    writer: &mut W,
+) -> Result<(), Box<dyn Error>> {
+    let (width, height) = (img.width(), img.height());
+    let colors = options.colors.unwrap_or(256).clamp(2, 256) as usize;
+    let (indices, palette, alpha) = if img.color().has_alpha() {
+        if options.palette.is_some() {
+            return Err("an rgb palette can not keep the alpha of the image".into());
+        }
+        // fully transparent pixels share one entry whatever their color
+        let pixels: Vec<[u8; 4]> = img
+            .to_rgba8()
+            .pixels()
+            .map(|px| if px[3] == 0 { [0; 4] } else { px.0 })
+            .collect();
+        let mut palette = median_cut(&pixels, colors);
+        // translucent entries first so the trns chunk stops at the last of them
+        palette.sort_by_key(|color| color[3]);
+        let indices = palette_indices(&pixels, &palette);
+        let alpha: Vec<u8> = palette.iter().map(|color| color[3]).collect();
+        let rgb = palette.iter().map(|&[r, g, b, _]| [r, g, b]).collect();
+        (indices, rgb, alpha)
+    } else {
+        let pixels: Vec<[u8; 3]> = img.to_rgb8().pixels().map(|px| px.0).collect();
+        let palette: Vec<[u8; 3]> = match &options.palette {
+            Some(palette) => palette.iter().map(|&(r, g, b)| [r, g, b]).collect(),
+            None => median_cut(&pixels, colors),
+        };
+        (palette_indices(&pixels, &palette), palette, Vec::new())
+    };
+    if palette.is_empty() || palette.len() > 256 {
+        return Err("the palette must have 1 to 256 colors".into());
+    }
+    let bits = match palette.len() {
+        0..=2 => 1,
+        3..=4 => 2,
+        5..=16 => 4,
+        _ => 8,
+    };
+    let mut encoder = png_encoder(writer, width, height, options);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::from_u8(bits).ok_or("invalid png bit depth")?);
+    encoder.set_palette(palette.concat());
+    let trns_len = alpha
+        .iter()
+        .rposition(|&a| a != 255)
+        .map_or(0, |last| last + 1);
+    if trns_len > 0 {
+        encoder.set_trns(alpha[..trns_len].to_vec());
+    }
+    encoder
+        .write_header()?
+        .write_image_data(&pack_rows(&indices, width as usize, bits))?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    #[test]
+    fn indexed_png_keeps_alpha() {
+        let px = [
+            [255, 0, 0, 255],
+            [0, 0, 255, 128],
+            [7, 8, 9, 0],
+            [0, 255, 0, 0],
+        ];
+        let img = RgbaImage::from_fn(2, 2, |x, y| Rgba(px[(y * 2 + x) as usize]));
+        let options = SaveOptions {
+            indexed: Some(true),
+            ..Default::default()
+        };
+        let mut png = Cursor::new(Vec::new());
+        write_img(img.into(), ImageFormat::Png, &options, &mut png).unwrap();
+        let decoded = image::load_from_memory(png.get_ref()).unwrap().to_rgba8();
+        let alpha: Vec<u8> = decoded.pixels().map(|px| px[3]).collect();
+        assert_eq!(alpha, [255, 128, 0, 0]);
+        assert_eq!(decoded.get_pixel(0, 0).0, [255, 0, 0, 255]);
+
+        let options = SaveOptions {
+            palette: Some(vec![(0, 0, 0), (255, 255, 255)]),
+            ..Default::default()
+        };
+        let img = DynamicImage::ImageRgba8(RgbaImage::new(2, 2));
+        assert!(write_img(img, ImageFormat::Png, &options, &mut png).is_err());
+    }
+}
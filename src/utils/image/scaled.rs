@@ -0,0 +1,179 @@
+use std::error::Error;
+
+use bytemuck::{cast_slice, pod_collect_to_vec};
+use fast_image_resize::images::{Image, ImageRef};
+use fast_image_resize::{FilterType, PixelType, ResizeAlg, ResizeOptions, Resizer};
+use image::DynamicImage;
+
+use crate::utils::core::convert::buffer;
+use crate::utils::image::baseline::BaselineJpeg;
+
+// Reduced size decoding for previews. Baseline jpegs are decoded straight at 1/2,
+// 1/4 or 1/8 of their size (see `BaselineJpeg`), everything else is decoded in full
+// and box resized.
+
+/// Shrinks an image to 1/`scale` of its size (rounded up) with a box filter.
+pub(crate) fn downscale(img: DynamicImage, scale: u32) -> Result<DynamicImage, Box<dyn Error>> {
+    if scale <= 1 {
+        return Ok(img);
+    }
+    let (width, height) = (img.width(), img.height());
+    let (w, h) = (width.div_ceil(scale), height.div_ceil(scale));
+    let resize = |src: &[u8], pixel_type: PixelType| -> Result<Vec<u8>, Box<dyn Error>> {
+        let src = ImageRef::new(width, height, src, pixel_type)?;
+        let mut dst = Image::new(w, h, pixel_type);
+        let options = ResizeOptions::new().resize_alg(ResizeAlg::Convolution(FilterType::Box));
+        Resizer::new().resize(&src, &mut dst, &options)?;
+        Ok(dst.into_vec())
+    };
+
+    macro_rules! shrink {
+        ($buf:expr, $variant:ident, $pixel_type:expr) => {{
+            let px = resize(cast_slice($buf.as_raw()), $pixel_type)?;
+            DynamicImage::$variant(buffer(w, h, pod_collect_to_vec(&px))?)
+        }};
+    }
+    Ok(match img {
+        DynamicImage::ImageLuma8(buf) => shrink!(buf, ImageLuma8, PixelType::U8),
+        DynamicImage::ImageLumaA8(buf) => shrink!(buf, ImageLumaA8, PixelType::U8x2),
+        DynamicImage::ImageRgb8(buf) => shrink!(buf, ImageRgb8, PixelType::U8x3),
+        DynamicImage::ImageRgba8(buf) => shrink!(buf, ImageRgba8, PixelType::U8x4),
+        DynamicImage::ImageLuma16(buf) => shrink!(buf, ImageLuma16, PixelType::U16),
+        DynamicImage::ImageLumaA16(buf) => shrink!(buf, ImageLumaA16, PixelType::U16x2),
+        DynamicImage::ImageRgb16(buf) => shrink!(buf, ImageRgb16, PixelType::U16x3),
+        DynamicImage::ImageRgba16(buf) => shrink!(buf, ImageRgba16, PixelType::U16x4),
+        DynamicImage::ImageRgb32F(buf) => shrink!(buf, ImageRgb32F, PixelType::F32x3),
+        DynamicImage::ImageRgba32F(buf) => shrink!(buf, ImageRgba32F, PixelType::F32x4),
+        img => {
+            let buf = img.to_rgba8();
+            shrink!(buf, ImageRgba8, PixelType::U8x4)
+        }
+    })
+}
+
+/// Decodes a baseline jpeg at 1/`scale` of its size (2, 4 or 8), rounded up.
+///
+/// # Returns
+/// Gray or rgb 8 bit image, None for anything the reduced decoder does not handle
+/// (other scales, progressive, arithmetic, 12 bit or cmyk jpegs and non jpegs).
+pub(crate) fn decode_scaled_jpeg(
+    bytes: &[u8],
+    scale: u32,
+) -> Result<Option<DynamicImage>, Box<dyn Error>> {
+    if !matches!(scale, 2 | 4 | 8) {
+        return Ok(None);
+    }
+    let Some(mut jpeg) = BaselineJpeg::new(bytes, scale)? else {
+        return Ok(None);
+    };
+    let mut px = Vec::with_capacity(jpeg.width * jpeg.height * jpeg.channels);
+    while let Some(band) = jpeg.next_band()? {
+        px.extend_from_slice(&band);
+    }
+    let (w, h) = (jpeg.width as u32, jpeg.height as u32);
+    Ok(Some(match jpeg.channels {
+        1 => DynamicImage::ImageLuma8(buffer(w, h, px)?),
+        _ => DynamicImage::ImageRgb8(buffer(w, h, px)?),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jpeg_encoder::{ColorType, Encoder, SamplingFactor};
+
+    fn gradient_jpeg(sampling: SamplingFactor, progressive: bool) -> Vec<u8> {
+        let (w, h) = (208usize, 128usize);
+        let px: Vec<u8> = (0..w * h)
+            .flat_map(|i| {
+                let (x, y) = (i % w, i / w);
+                [x as u8, (y * 2) as u8, ((x + y) / 2) as u8]
+            })
+            .collect();
+        let mut out = Vec::new();
+        let mut encoder = Encoder::new(&mut out, 95);
+        encoder.set_sampling_factor(sampling);
+        encoder.set_restart_interval(3);
+        encoder.set_progressive(progressive);
+        encoder
+            .encode(&px, w as u16, h as u16, ColorType::Rgb)
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn scaled_jpeg_matches_resize() {
+        for sampling in [SamplingFactor::F_2_2, SamplingFactor::F_1_1] {
+            let jpeg = gradient_jpeg(sampling, false);
+            let full = image::load_from_memory(&jpeg).unwrap();
+            for scale in [2, 4, 8] {
+                let img = decode_scaled_jpeg(&jpeg, scale).unwrap().unwrap();
+                let reference = downscale(full.clone(), scale).unwrap();
+                assert_eq!(
+                    (img.width(), img.height()),
+                    (reference.width(), reference.height())
+                );
+                let (a, b) = (img.as_bytes(), reference.as_bytes());
+                let diff = a
+                    .iter()
+                    .zip(b)
+                    .map(|(&x, &y)| x.abs_diff(y) as u32)
+                    .sum::<u32>();
+                // the scaled idct is not the resize filter, so steep gradients differ a bit
+                let mean = diff / a.len() as u32;
+                assert!(mean < 5, "scale {} differs by {} on average", scale, mean);
+            }
+        }
+        let progressive = gradient_jpeg(SamplingFactor::F_2_2, true);
+        assert!(decode_scaled_jpeg(&progressive, 2).unwrap().is_none());
+    }
+
+    /// Offsets of the counts of every huffman table, dc tables first.
+    fn huffman_tables(jpeg: &[u8]) -> (Vec<usize>, Vec<usize>) {
+        let (mut dc, mut ac) = (Vec::new(), Vec::new());
+        let mut pos = 2;
+        while jpeg[pos + 1] != 0xDA {
+            let len = u16::from_be_bytes([jpeg[pos + 2], jpeg[pos + 3]]) as usize;
+            if jpeg[pos + 1] == 0xC4 {
+                let mut table = pos + 4;
+                while table < pos + 2 + len {
+                    let total: usize = jpeg[table + 1..table + 17]
+                        .iter()
+                        .map(|&c| c as usize)
+                        .sum();
+                    match jpeg[table] >> 4 {
+                        0 => dc.push(table + 1),
+                        _ => ac.push(table + 1),
+                    }
+                    table += 17 + total;
+                }
+            }
+            pos += 2 + len;
+        }
+        (dc, ac)
+    }
+
+    #[test]
+    fn malformed_huffman_tables_fail() {
+        let jpeg = gradient_jpeg(SamplingFactor::F_2_2, false);
+        let (dc, ac) = huffman_tables(&jpeg);
+
+        // three codes of length 1 do not fit
+        let mut overfull = jpeg.clone();
+        let counts = ac[0];
+        let len = (1..16).find(|&len| overfull[counts + len] >= 3).unwrap();
+        overfull[counts + len] -= 3;
+        overfull[counts] += 3;
+        assert!(decode_scaled_jpeg(&overfull, 2).is_err());
+
+        // a dc difference of 16 bits
+        let mut dc_size = jpeg.clone();
+        dc_size[dc[0] + 16] = 16;
+        assert!(decode_scaled_jpeg(&dc_size, 2).is_err());
+
+        // an ac coefficient of 11 bits
+        let mut ac_size = jpeg;
+        ac_size[ac[0] + 16] = 0x0B;
+        assert!(decode_scaled_jpeg(&ac_size, 2).is_err());
+    }
+}
@@ -1,18 +1,160 @@
 use filebuffer::FileBuffer;
-use pyo3::exceptions::{PyOSError};
+use image::{ExtendedColorType, ImageDecoder, ImageFormat};
+use pyo3::exceptions::PyOSError;
 use pyo3::prelude::*;
 use std::io::Cursor;
 use std::path::Path;
 use image::io::Reader as ImageReader;
 
+use crate::utils::image::metadata::{jpeg_components, jpeg_dpi, png_chunk, png_dpi, tiff_tags};
+use crate::utils::image::psd::{be_u32, PsdFile, PsdHeader, PSD_MAGIC};
+
 pub fn path_to_size(img_path: &Path) -> PyResult<(u32, u32)> {
     let file_bytes = match FileBuffer::open(img_path) {
         Ok(buffer) => buffer.to_vec(),
         Err(err) => return Err(PyOSError::new_err(format!("Error reading file: {}", err))),
     };
+    if file_bytes.starts_with(&PSD_MAGIC) {
+        return match PsdHeader::parse(&file_bytes) {
+            Ok(header) => Ok((header.width as u32, header.height as u32)),
+            Err(err) => Err(PyOSError::new_err(format!("Error decode file: {}", err))),
+        };
+    }
     let mut cursor: Cursor<&[u8]> = Cursor::new(&file_bytes);
     match ImageReader::new(&mut cursor).with_guessed_format()?.into_dimensions(){ 
         Ok(dim)=>Ok(dim),
         Err(err)=>Err(PyOSError::new_err(format!("Error decode file: {}", err))),
     }
 }
+
+/// Image properties read from the file headers, the pixel data is not decoded.
+#[pyclass(get_all)]
+pub struct ImgInfo {
+    pub width: u32,
+    pub height: u32,
+    /// channels stored in the file, including alpha and extra psd channels
+    pub channels: u8,
+    /// bits per channel
+    pub bit_depth: u8,
+    /// "gray", "gray_alpha", "rgb", "rgba", "cmyk", "lab", "indexed", "bitmap"...
+    pub color_mode: String,
+    pub format: String,
+    /// (horizontal, vertical) dots per inch, None when the file does not store it
+    pub dpi: Option<(f32, f32)>,
+    pub has_icc_profile: bool,
+    /// exif orientation 1-8, None when the file has no orientation tag
+    pub orientation: Option<u8>,
+}
+
+pub fn path_to_info(img_path: &Path) -> PyResult<ImgInfo> {
+    let file_bytes = FileBuffer::open(img_path)
+        .map_err(|err| PyOSError::new_err(format!("Error reading file: {}", err)))?;
+    if file_bytes.starts_with(&PSD_MAGIC) {
+        return psd_info(&file_bytes)
+            .map_err(|err| PyOSError::new_err(format!("Error decode file: {}", err)));
+    }
+    img_info(&file_bytes).map_err(|err| PyOSError::new_err(format!("Error decode file: {}", err)))
+}
+
+fn psd_info(bytes: &[u8]) -> Result<ImgInfo, Box<dyn std::error::Error>> {
+    let psd = PsdFile::parse(bytes)?;
+    let header = psd.header;
+    // resolution info stores 16.16 fixed point pixels per inch
+    let dpi = psd.resource(1005).filter(|data| data.len() >= 12).map(|data| {
+        (
+            be_u32(&data[..4]) as f32 / 65536.0,
+            be_u32(&data[8..12]) as f32 / 65536.0,
+        )
+    });
+    Ok(ImgInfo {
+        width: header.width as u32,
+        height: header.height as u32,
+        channels: header.channels as u8,
+        bit_depth: header.depth as u8,
+        color_mode: header.color_mode_name().to_string(),
+        format: if header.psb { "psb" } else { "psd" }.to_string(),
+        dpi,
+        has_icc_profile: psd.resource(1039).is_some(),
+        orientation: psd.orientation(),
+    })
+}
+
+fn img_info(bytes: &[u8]) -> Result<ImgInfo, Box<dyn std::error::Error>> {
+    let reader = image::ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+    let format = reader.format().ok_or("Unsupported image format")?;
+    let mut decoder = reader.into_decoder()?;
+    let (width, height) = decoder.dimensions();
+    let color_type = decoder.original_color_type();
+    let mut channels = color_type.channel_count();
+    let mut bit_depth = (color_type.bits_per_pixel() / channels.max(1) as u16) as u8;
+    let mut color_mode = color_mode_name(color_type).to_string();
+    let has_icc_profile = decoder.icc_profile()?.is_some();
+    let exif = decoder.exif_metadata()?;
+
+    let mut tags = exif.as_deref().and_then(tiff_tags);
+    let dpi = match format {
+        ImageFormat::Png => {
+            // palette images are expanded by the decoder, report what is stored
+            if let Some(ihdr) = png_chunk(bytes, b"IHDR").filter(|ihdr| ihdr.len() >= 10) {
+                if ihdr[9] == 3 {
+                    channels = 1;
+                    bit_depth = ihdr[8];
+                    color_mode = "indexed".to_string();
+                }
+            }
+            png_dpi(bytes)
+        }
+        ImageFormat::Jpeg => {
+            if let Some((precision, components)) = jpeg_components(bytes) {
+                channels = components;
+                bit_depth = precision;
+                if components == 4 {
+                    color_mode = "cmyk".to_string();
+                }
+            }
+            jpeg_dpi(bytes)
+        }
+        ImageFormat::Tiff => {
+            tags = tiff_tags(bytes);
+            tags.as_ref().and_then(|tags| tags.dpi)
+        }
+        _ => tags.as_ref().and_then(|tags| tags.dpi),
+    };
+    Ok(ImgInfo {
+        width,
+        height,
+        channels,
+        bit_depth,
+        color_mode,
+        format: format!("{:?}", format).to_lowercase(),
+        dpi,
+        has_icc_profile,
+        orientation: tags.and_then(|tags| tags.orientation),
+    })
+}
+
+fn color_mode_name(color_type: ExtendedColorType) -> &'static str {
+    match color_type {
+        ExtendedColorType::A8 => "alpha",
+        ExtendedColorType::L1
+        | ExtendedColorType::L2
+        | ExtendedColorType::L4
+        | ExtendedColorType::L8
+        | ExtendedColorType::L16 => "gray",
+        ExtendedColorType::La1
+        | ExtendedColorType::La2
+        | ExtendedColorType::La4
+        | ExtendedColorType::La8
+        | ExtendedColorType::La16 => "gray_alpha",
+        ExtendedColorType::Rgba1
+        | ExtendedColorType::Rgba2
+        | ExtendedColorType::Rgba4
+        | ExtendedColorType::Rgba8
+        | ExtendedColorType::Rgba16
+        | ExtendedColorType::Bgra8
+        | ExtendedColorType::Rgba32F => "rgba",
+        ExtendedColorType::Cmyk8 | ExtendedColorType::Cmyk16 => "cmyk",
+        ExtendedColorType::Unknown(_) => "unknown",
+        _ => "rgb",
+    }
+}
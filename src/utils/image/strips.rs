@@ -0,0 +1,283 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::ops::Range;
+use std::path::Path;
+
+use filebuffer::FileBuffer;
+use image::{DynamicImage, ImageFormat};
+
+use crate::utils::core::convert::buffer;
+use crate::utils::image::baseline::BaselineJpeg;
+use crate::utils::image::native::NativeImage;
+use crate::utils::image::psd::{PsdFile, PsdHeader, PSD_MAGIC};
+use crate::utils::image::region::{png_image, Region};
+
+// Strip by strip reading of very tall images with bounded memory. Png rows and
+// baseline jpeg mcu rows are streamed and psd rows are unpacked straight from the
+// mapped file, so only the current strip is held in memory. Formats that can not be
+// decoded by rows (progressive jpeg, interlaced png...) are refused.
+
+enum Source {
+    Png {
+        reader: Box<png::Reader<BufReader<File>>>,
+        rows: Rows,
+        channels: usize,
+        sixteen: bool,
+    },
+    Jpeg {
+        decoder: Box<BaselineJpeg<FileBuffer>>,
+        rows: Rows,
+    },
+    Psd {
+        bytes: FileBuffer,
+        /// Row table of the merged image, read once.
+        rle_rows: Vec<Range<usize>>,
+    },
+}
+
+/// Decoded rows starting at row `first`, kept for the overlap of the next strip.
+#[derive(Default)]
+struct Rows {
+    rows: VecDeque<Vec<u8>>,
+    first: usize,
+}
+
+impl Rows {
+    /// Samples of rows `top..bottom`, `read` appends the next rows of the image.
+    fn strip(
+        &mut self,
+        top: usize,
+        bottom: usize,
+        mut read: impl FnMut(&mut VecDeque<Vec<u8>>) -> Result<(), Box<dyn Error>>,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        while self.first + self.rows.len() < bottom {
+            read(&mut self.rows)?;
+        }
+        // strips move down, rows above the strip are never needed again
+        while self.first < top {
+            self.rows.pop_front();
+            self.first += 1;
+        }
+        Ok(self
+            .rows
+            .iter()
+            .take(bottom - top)
+            .flatten()
+            .copied()
+            .collect())
+    }
+}
+
+/// A strip like the result of `region_open`, or native samples for DYNAMIC reads of
+/// cmyk, lab and multichannel images.
+pub(crate) enum Strip {
+    Image(DynamicImage, u8),
+    Native(NativeImage),
+}
+
+pub(crate) struct StripReader {
+    source: Source,
+    pub width: usize,
+    pub height: usize,
+    strip_height: usize,
+    overlap: usize,
+    native: bool,
+    /// Top row of the next strip without its overlap.
+    next: usize,
+}
+
+impl StripReader {
+    /// Opens `path` for strips of `strip_height` rows, every strip is extended by up
+    /// to `overlap` rows of its neighbours above and below. With `native` cmyk, lab
+    /// and multichannel images keep their samples like DYNAMIC reads.
+    pub fn open(
+        path: &Path,
+        strip_height: usize,
+        overlap: usize,
+        native: bool,
+    ) -> Result<StripReader, Box<dyn Error>> {
+        if strip_height == 0 {
+            return Err("strip height must be at least 1".into());
+        }
+        let bytes = FileBuffer::open(path).map_err(|err| Box::new(err) as Box<dyn Error>)?;
+        let png = image::guess_format(&bytes).is_ok_and(|format| format == ImageFormat::Png);
+        let source = if bytes.starts_with(&PSD_MAGIC) {
+            let rle_rows = PsdFile::parse(&bytes)?.into_rle_rows();
+            Source::Psd { bytes, rle_rows }
+        } else if png {
+            let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+            decoder.set_transformations(png::Transformations::EXPAND);
+            let reader = decoder.read_info()?;
+            // interlaced passes span the whole image
+            if reader.info().interlaced {
+                return Err("interlaced png can not be read in strips".into());
+            }
+            let (color, depth) = reader.output_color_type();
+            Source::Png {
+                reader: Box::new(reader),
+                rows: Rows::default(),
+                channels: color.samples(),
+                sixteen: depth == png::BitDepth::Sixteen,
+            }
+        } else {
+            match BaselineJpeg::new(bytes, 1)? {
+                Some(decoder) => Source::Jpeg {
+                    decoder: Box::new(decoder),
+                    rows: Rows::default(),
+                },
+                None => return Err("only png, psd and baseline jpeg can be read in strips".into()),
+            }
+        };
+        let (width, height) = match &source {
+            Source::Png { reader, .. } => {
+                let info = reader.info();
+                (info.width as usize, info.height as usize)
+            }
+            Source::Jpeg { decoder, .. } => (decoder.width, decoder.height),
+            Source::Psd { bytes, .. } => {
+                let header = PsdHeader::parse(bytes)?;
+                (header.width, header.height)
+            }
+        };
+        Ok(StripReader {
+            source,
+            width,
+            height,
+            strip_height,
+            overlap,
+            native,
+            next: 0,
+        })
+    }
+
+    /// Decodes the next strip.
+    ///
+    /// # Returns
+    /// The first image row of the strip (overlap included) and the strip, None
+    /// after the last one.
+    pub fn next_strip(&mut self) -> Result<Option<(usize, Strip)>, Box<dyn Error>> {
+        if self.next >= self.height {
+            return Ok(None);
+        }
+        let top = self.next.saturating_sub(self.overlap);
+        let bottom = (self.next + self.strip_height + self.overlap).min(self.height);
+        self.next += self.strip_height;
+        let region = Region {
+            x: 0,
+            y: top,
+            width: self.width,
+            height: bottom - top,
+        };
+        let (w, h) = (region.width as u32, region.height as u32);
+
+        let strip = match &mut self.source {
+            Source::Png {
+                reader,
+                rows,
+                channels,
+                sixteen,
+            } => {
+                let px = rows.strip(top, bottom, |rows| {
+                    let row = reader.next_row()?.ok_or("png is truncated")?;
+                    rows.push_back(row.data().to_vec());
+                    Ok(())
+                })?;
+                Strip::Image(png_image(px, w, h, *channels, *sixteen)?, *channels as u8)
+            }
+            Source::Jpeg { decoder, rows } => {
+                let row_len = decoder.width * decoder.channels;
+                let px = rows.strip(top, bottom, |rows| {
+                    let band = decoder.next_band()?.ok_or("jpeg is truncated")?;
+                    rows.extend(band.chunks_exact(row_len).map(<[u8]>::to_vec));
+                    Ok(())
+                })?;
+                let channels = decoder.channels as u8;
+                match channels {
+                    1 => Strip::Image(DynamicImage::ImageLuma8(buffer(w, h, px)?), channels),
+                    _ => Strip::Image(DynamicImage::ImageRgb8(buffer(w, h, px)?), channels),
+                }
+            }
+            Source::Psd { bytes, rle_rows } => {
+                // only the header is parsed again, the pixels stay in the mapped file
+                let psd = PsdFile::parse_with_rows(bytes, Some(rle_rows))?;
+                let native = if self.native {
+                    psd.decode_native_region(region)?
+                } else {
+                    None
+                };
+                match native {
+                    Some(native) => Strip::Native(native),
+                    None => {
+                        let (img, channels) = psd.decode_region(region)?;
+                        Strip::Image(img, channels)
+                    }
+                }
+            }
+        };
+        Ok(Some((top, strip)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::image::decode::img_open;
+
+    #[test]
+    fn psd_strips_with_overlap() {
+        // 2x2 rgb, 16 bit, rle
+        let path = Path::new("tests/fixtures/rgb16_rle.psd");
+        let (full, _) = img_open(&std::fs::read(path).unwrap(), false, false, 1).unwrap();
+        let mut reader = StripReader::open(path, 1, 1, false).unwrap();
+        let mut tops = Vec::new();
+        while let Some((y, Strip::Image(img, _))) = reader.next_strip().unwrap() {
+            let crop = full.crop_imm(0, y as u32, 2, img.height());
+            assert_eq!(img.to_rgb16(), crop.to_rgb16());
+            tops.push((y, img.height()));
+        }
+        assert_eq!(tops, [(0, 2), (0, 2)]);
+    }
+
+    #[test]
+    fn jpeg_strips_match_full_decode() {
+        use jpeg_encoder::{ColorType, Encoder, SamplingFactor};
+
+        let (w, h) = (37usize, 70usize);
+        let px: Vec<u8> = (0..w * h)
+            .flat_map(|i| {
+                let (x, y) = (i % w, i / w);
+                [(x * 6) as u8, (y * 3) as u8, ((x + y) * 2) as u8]
+            })
+            .collect();
+        let path = std::env::temp_dir().join("pepeline_jpeg_strips.jpg");
+        for sampling in [SamplingFactor::F_2_2, SamplingFactor::F_1_1] {
+            let mut encoder = Encoder::new_file(&path, 90).unwrap();
+            encoder.set_sampling_factor(sampling);
+            encoder.set_restart_interval(2);
+            encoder
+                .encode(&px, w as u16, h as u16, ColorType::Rgb)
+                .unwrap();
+            let full = image::open(&path).unwrap().to_rgb8();
+            let mut reader = StripReader::open(&path, 25, 3, false).unwrap();
+            let mut tops = Vec::new();
+            while let Some((y, Strip::Image(img, _))) = reader.next_strip().unwrap() {
+                let crop = image::imageops::crop_imm(&full, 0, y as u32, w as u32, img.height())
+                    .to_image();
+                let strip = img.to_rgb8();
+                let mut diff = strip.iter().zip(crop.iter());
+                assert!(diff.all(|(&a, &b)| a.abs_diff(b) <= 3));
+                tops.push((y, img.height()));
+            }
+            assert_eq!(tops, [(0, 28), (22, 31), (47, 23)]);
+        }
+        let mut encoder = Encoder::new_file(&path, 90).unwrap();
+        encoder.set_progressive(true);
+        encoder
+            .encode(&px, w as u16, h as u16, ColorType::Rgb)
+            .unwrap();
+        assert!(StripReader::open(&path, 25, 0, false).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
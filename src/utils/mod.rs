@@ -1,7 +1,19 @@
 pub(crate) mod image {
+    pub mod archive;
+    pub mod baseline;
     pub mod decode;
+    pub mod frames;
+    pub mod icc;
+    pub mod metadata;
+    pub mod native;
+    pub mod palette;
+    pub mod psd;
+    pub mod psd_write;
+    pub mod region;
     pub mod save;
+    pub mod scaled;
     pub mod size_decode;
+    pub mod strips;
 }
 
 pub(crate) mod core {
@@ -9,6 +21,7 @@ pub(crate) mod core {
     pub mod convert;
     pub mod cvt_color_float;
     pub mod cvt_constants;
+    pub mod dither;
     pub mod enums;
     pub mod noise;
 }